@@ -23,8 +23,11 @@ use cashu_sdk::{
 };
 use database::surrealdb::engine::remote::ws::Client;
 use lightning_node_client::{
-    get_lightning_node_client,
-    proto::{CreateBolt11InvoiceRequest, PayInvoiceRequest},
+    error_reason, get_lightning_node_client,
+    proto::{
+        CreateBolt11InvoiceRequest, ErrorReason, EstimateRouteFeeRequest, PayInvoiceRequest,
+        PaymentStatus,
+    },
 };
 use log::info;
 use std::{fmt::Write, str::FromStr, sync::Arc};
@@ -37,6 +40,19 @@ use crate::{
 
 use super::models::{FaucetQueryParams, FaucetResponse, MintParams, RequestMintParams};
 
+/// Maps a failed lightning node call to the response status.
+fn node_error_status(status: &tonic::Status) -> StatusCode {
+    match error_reason(status) {
+        ErrorReason::InvalidArgument | ErrorReason::InvalidAmount | ErrorReason::InvalidInvoice => {
+            StatusCode::BAD_REQUEST
+        }
+        ErrorReason::NodeNotRunning | ErrorReason::ConnectionFailed | ErrorReason::SyncFailed => {
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
 pub async fn info(
     Path(mint_id): Path<String>,
     cashu: Extension<Arc<CashuService<Client>>>,
@@ -81,9 +97,13 @@ pub async fn get_request_mint(
     config: Extension<Arc<CashuApiConfig>>,
     mint_params: Query<RequestMintParams>,
 ) -> Result<Json<RequestMintResponse>, StatusCode> {
-    let mut node_client = get_lightning_node_client(config.lightning_node_endpoint.clone(), false)
-        .await
-        .unwrap();
+    let mut node_client = get_lightning_node_client(
+        config.lightning_node_endpoint.clone(),
+        false,
+        &config.lightning_node_credentials(),
+    )
+    .await
+    .unwrap();
 
     let invoice = match node_client
         .create_bolt11_invoice(CreateBolt11InvoiceRequest {
@@ -261,15 +281,40 @@ pub async fn post_split(
 
 pub async fn post_check_fee(
     Path(_): Path<String>,
+    config: Extension<Arc<CashuApiConfig>>,
     payload: Json<CheckFeesRequest>,
 ) -> Result<Json<CheckFeesResponse>, StatusCode> {
-    // let invoice = LnInvoice::from_str(&payload.pr)?;
-    // todo
-    let _amount_msat = payload.pr.amount_milli_satoshis().unwrap();
-    let amount_sat = 0;
-    let amount = Amount::from(amount_sat);
+    let mut node_client = match get_lightning_node_client(
+        config.lightning_node_endpoint.clone(),
+        false,
+        &config.lightning_node_credentials(),
+    )
+    .await
+    {
+        Ok(client) => client,
+        Err(err) => {
+            info!("Cannot connect to lightning node: {}", err);
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
+        }
+    };
+
+    let estimate = match node_client
+        .estimate_route_fee(EstimateRouteFeeRequest {
+            bolt11_invoice: payload.pr.to_string(),
+            destination: String::new(),
+            amount_msat: payload.pr.amount_milli_satoshis().unwrap_or_default(),
+        })
+        .await
+    {
+        Ok(res) => res.into_inner(),
+        Err(err) => {
+            info!("Could not estimate route fee: {}", err.message());
+            return Err(node_error_status(&err));
+        }
+    };
 
-    let fee = amount;
+    // Round up, the mint must not reserve less than the route costs
+    let fee = Amount::from((estimate.fee_msat + 999) / 1000);
 
     Ok(Json(CheckFeesResponse { fee }))
 }
@@ -300,9 +345,13 @@ pub async fn post_melt(
     }
     let inv = payload.pr.clone();
 
-    let mut node_client = get_lightning_node_client(config.lightning_node_endpoint.clone(), false)
-        .await
-        .unwrap();
+    let mut node_client = get_lightning_node_client(
+        config.lightning_node_endpoint.clone(),
+        false,
+        &config.lightning_node_credentials(),
+    )
+    .await
+    .unwrap();
 
     info!("Paying invoice");
     let pay_res = node_client
@@ -313,9 +362,15 @@ pub async fn post_melt(
         .await;
 
     match pay_res {
-        Ok(_) => {} //Ok(res.into_inner())},
-        Err(_) => {
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        Ok(res) => {
+            if res.into_inner().status() == PaymentStatus::Failed {
+                info!("Invoice payment failed");
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+        Err(err) => {
+            info!("Invoice payment failed: {}", err.message());
+            return Err(node_error_status(&err));
         }
     };
 
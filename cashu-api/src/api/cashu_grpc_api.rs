@@ -1,40 +1,60 @@
 use std::sync::Arc;
 
-use bank_grpc_api::*;
-use surrealdb::engine::remote::ws::Client;
+use cashu_grpc_api::*;
+use database::surrealdb::engine::remote::ws::Client;
+use grpc_auth::{authorize, Scope};
 use tonic::{Request, Response, Status};
 
 use crate::cashu::CashuService;
 
-pub mod bank_grpc_api {
-    tonic::include_proto!("bank_service");
+pub mod cashu_grpc_api {
+    tonic::include_proto!("cashu_service");
 }
 
-pub struct BankService {
+pub struct CashuGrpcService {
     pub cashu_service: Arc<CashuService<Client>>,
 }
 
 #[tonic::async_trait]
-impl bank_server::Bank for BankService {
+impl cashu_server::Cashu for CashuGrpcService {
     async fn internal_token_mint(
         &self,
         request: Request<InternalTokenMintRequest>,
     ) -> Result<Response<InternalTokenMintResponse>, Status> {
+        authorize(&request, Scope::Payments)?;
+
         let r = request.into_inner();
-        let token = self
-            .cashu_service
-            .mint_token(&r.mint_id, r.amount_sat)
-            .await
-            .unwrap();
+        if !self.cashu_service.mints.contains_key(&r.mint_id) {
+            return Err(Status::new(tonic::Code::NotFound, "Mint not found"));
+        }
 
-        Ok(Response::new(InternalTokenMintResponse { token }))
+        let amount_msat = match r.amount_sat.checked_mul(1000) {
+            Some(amount_msat) => amount_msat,
+            None => return Err(Status::new(tonic::Code::InvalidArgument, "Invalid amount")),
+        };
+
+        match self.cashu_service.mint_token(&r.mint_id, amount_msat).await {
+            Ok(token) => Ok(Response::new(InternalTokenMintResponse { token })),
+            Err(err) => Err(Status::new(tonic::Code::Internal, err.to_string())),
+        }
     }
 
     async fn create_mint(
         &self,
         request: Request<CreateMintRequest>,
     ) -> Result<Response<CreateMintResponse>, Status> {
+        authorize(&request, Scope::Admin)?;
+
         let r = request.into_inner();
+        let max_order = match r.max_order.try_into() {
+            Ok(max_order) => max_order,
+            Err(_) => {
+                return Err(Status::new(
+                    tonic::Code::InvalidArgument,
+                    "Invalid max order",
+                ))
+            }
+        };
 
         match self
             .cashu_service
@@ -43,13 +63,13 @@ impl bank_server::Bank for BankService {
                 r.version.as_str(),
                 r.secret.as_str(),
                 r.derivation_path.as_str(),
-                r.max_order.try_into().unwrap(),
+                max_order,
                 r.min_fee_reserve_msat,
                 r.percent_fee_reserve,
                 Some(r.description),
                 Some(r.description_long),
                 Some(r.contact),
-                Some(r.motd)
+                Some(r.motd),
             )
             .await
         {
@@ -1,3 +1,3 @@
 pub mod cashu_api;
-//pub mod cashu_grpc_api;
+pub mod cashu_grpc_api;
 pub mod models;
\ No newline at end of file
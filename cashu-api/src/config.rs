@@ -1,8 +1,58 @@
+use anyhow::Result;
+use grpc_auth::{tls, GrpcCredentials, Scope};
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct CashuApiConfig {
     pub lightning_node_endpoint: String,
     pub cashu_mint_url: String,
-    pub cashu_api_port: u16
+    pub cashu_api_port: u16,
+    /// The internal gRPC service is only served when a port is set
+    pub cashu_grpc_port: Option<u16>,
+    /// Comma separated `scope:token` pairs
+    pub grpc_auth_tokens: Option<String>,
+    /// Serves gRPC without authentication, required when no tokens or client CA are set
+    pub grpc_auth_disabled: Option<bool>,
+    /// Serves gRPC over TLS with a certificate generated in the TLS dir on the first run
+    pub cashu_grpc_tls: Option<bool>,
+    pub cashu_grpc_tls_dir: Option<String>,
+    /// Comma separated hostnames added to the generated certificate besides localhost
+    pub cashu_grpc_tls_hostnames: Option<String>,
+    /// PEM CA which signs client certificates, enables mutual TLS
+    pub cashu_grpc_tls_client_ca: Option<String>,
+    /// Scope granted to clients with a certificate, required with a client CA
+    pub cashu_grpc_tls_client_scope: Option<String>,
+    pub lightning_node_auth_token: Option<String>,
+    pub lightning_node_tls_cert: Option<String>,
+    pub lightning_node_tls_client_cert: Option<String>,
+    pub lightning_node_tls_client_key: Option<String>,
+}
+
+impl CashuApiConfig {
+    pub fn grpc_tls_dir(&self) -> &str {
+        self.cashu_grpc_tls_dir
+            .as_deref()
+            .unwrap_or("./app_data/cashu_tls")
+    }
+
+    pub fn grpc_tls_hostnames(&self) -> Vec<String> {
+        tls::parse_hostnames(self.cashu_grpc_tls_hostnames.as_deref())
+    }
+
+    pub fn grpc_client_cert_scope(&self) -> Result<Option<Scope>> {
+        tls::client_cert_scope(
+            self.cashu_grpc_tls.unwrap_or(false),
+            self.cashu_grpc_tls_client_ca.as_deref(),
+            self.cashu_grpc_tls_client_scope.as_deref(),
+        )
+    }
+
+    pub fn lightning_node_credentials(&self) -> GrpcCredentials {
+        GrpcCredentials {
+            auth_token: self.lightning_node_auth_token.clone(),
+            tls_cert_path: self.lightning_node_tls_cert.clone(),
+            tls_client_cert_path: self.lightning_node_tls_client_cert.clone(),
+            tls_client_key_path: self.lightning_node_tls_client_key.clone(),
+        }
+    }
 }
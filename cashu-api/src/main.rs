@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 
 use anyhow::Result;
 use axum::{
@@ -6,17 +6,21 @@ use axum::{
     Extension, Router,
 };
 use config::CashuApiConfig;
-use database::{config::SurrealDbConfig, init_db};
+use database::{config::SurrealDbConfig, init_db, processed_events::ProcessedEventStore};
 use dotenv::dotenv;
-use events::config::RabbitMqConfig;
+use grpc_auth::{tls, ServerAuthInterceptor};
 use lightning_node_client::get_lightning_node_client;
 use log::info;
 use repositories::cashu_repository::CashuMintReporitory;
 use services::payment_received_service::PaymentReceivedService;
+use tonic::transport::Server;
 use tower::ServiceBuilder;
 use tower_http::cors::{Any, CorsLayer};
 
-use crate::api::cashu_api;
+use crate::api::{
+    cashu_api,
+    cashu_grpc_api::{cashu_grpc_api::cashu_server::CashuServer, CashuGrpcService},
+};
 
 mod api;
 mod cashu;
@@ -31,17 +35,23 @@ async fn main() -> Result<()> {
     env_logger::init();
 
     let config = envy::from_env::<CashuApiConfig>().unwrap();
-    let rabbitmq_config = envy::from_env::<RabbitMqConfig>().unwrap();
     let db_config = envy::from_env::<SurrealDbConfig>().unwrap();
 
-    let events = events::lightning_node_events::LightningNodeEvents::new(rabbitmq_config).await?;
-    let subscribe_node_client =
-        get_lightning_node_client(config.lightning_node_endpoint.clone(), true).await?;
+    let events = events::event_bus::from_env().await?;
+    let subscribe_node_client = get_lightning_node_client(
+        config.lightning_node_endpoint.clone(),
+        true,
+        &config.lightning_node_credentials(),
+    )
+    .await?;
 
     let database = init_db(db_config, "walletka", "cashu").await?;
 
     let payment_received_service = PaymentReceivedService::new(subscribe_node_client);
-    payment_received_service.subscribe(events);
+    payment_received_service.subscribe(
+        events.clone(),
+        Arc::new(ProcessedEventStore::new(database.clone())),
+    );
 
     let cashu_repository = Arc::new(CashuMintReporitory::new(database));
 
@@ -51,6 +61,37 @@ async fn main() -> Result<()> {
             .unwrap(),
     );
 
+    if let Some(grpc_port) = config.cashu_grpc_port {
+        let auth = ServerAuthInterceptor::from_config(
+            config.grpc_auth_tokens.as_deref(),
+            config.grpc_client_cert_scope()?,
+            config.grpc_auth_disabled.unwrap_or(false),
+        )?;
+        let mut server = Server::builder();
+        if config.cashu_grpc_tls.unwrap_or(false) {
+            server = server.tls_config(tls::server_tls_config(
+                Path::new(config.grpc_tls_dir()),
+                &config.grpc_tls_hostnames(),
+                config.cashu_grpc_tls_client_ca.as_deref(),
+            )?)?;
+        }
+        let cashu_service = CashuServer::with_interceptor(
+            CashuGrpcService {
+                cashu_service: cashu.clone(),
+            },
+            auth,
+        );
+
+        tokio::spawn(async move {
+            info!("Starting grpc server at :{}", grpc_port);
+            server
+                .add_service(cashu_service)
+                .serve(format!("0.0.0.0:{}", grpc_port).parse().unwrap())
+                .await
+                .unwrap();
+        });
+    }
+
     let cors = CorsLayer::new()
         // allow `GET` and `POST` when accessing the resource
         .allow_methods(Any)
@@ -69,7 +110,9 @@ async fn main() -> Result<()> {
         // todo .route("/check", post().to(cashu_api::post_check))
         .route("/:mint_id/checkfees", post(cashu_api::post_check_fee))
         .route("/:mint_id/faucet", get(cashu_api::faucet))
+        .route("/health", get(events::health::health))
         .layer(ServiceBuilder::new().layer(cors))
+        .layer(Extension(events))
         .layer(Extension(cashu.clone()))
         .layer(Extension(Arc::new(config.clone())))
         .into_make_service();
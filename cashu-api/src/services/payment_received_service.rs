@@ -1,29 +1,47 @@
+use std::sync::Arc;
+
 use anyhow::Result;
-use events::lightning_node_events::{LightningNodeEvents, PaymentReceivedProcessor};
-use lightning_node_client::proto::node_client::NodeClient;
+use events::{
+    event_bus::EventBus,
+    idempotency::{Deduplicated, IdempotencyStore},
+    lightning_node_events::{PaymentReceivedHandler, PaymentReceivedProcessor},
+    messages::CustomTlvRecord,
+};
+use lightning_node_client::LightningNodeClient;
 use log::{info, warn};
-use tonic::{async_trait, transport::Channel};
+use tonic::async_trait;
+
+const RECEIVED_PAYMENTS_QUEUE: &str = "walletka.cashu.received_payments";
 
 struct PaymentReceivedCallback {}
 
 pub struct PaymentReceivedService {
-    pub client: NodeClient<Channel>,
+    pub client: LightningNodeClient,
 }
 
 impl PaymentReceivedService {
-    pub fn new(client: NodeClient<Channel>) -> Self {
+    pub fn new(client: LightningNodeClient) -> Self {
         Self { client }
     }
 
-    pub fn subscribe(&self, events: LightningNodeEvents) {
+    pub fn subscribe(
+        &self,
+        events: Arc<dyn EventBus>,
+        processed_events: Arc<dyn IdempotencyStore + Send + Sync>,
+    ) {
+        let handler = Deduplicated::new(
+            RECEIVED_PAYMENTS_QUEUE,
+            Arc::new(PaymentReceivedHandler::new(Arc::new(
+                PaymentReceivedCallback {},
+            ))),
+            processed_events,
+        );
+
         tokio::spawn(async move {
             info!("Subscribing lightning payments");
 
             events
-                .subscribe_received_payments(
-                    "walletka.cashu.received_payments",
-                    PaymentReceivedCallback {},
-                )
+                .subscribe(RECEIVED_PAYMENTS_QUEUE, Arc::new(handler))
                 .await
                 .unwrap();
 
@@ -38,6 +56,8 @@ impl PaymentReceivedProcessor for PaymentReceivedCallback {
         &self,
         payment_hash: String,
         amount_msat: u64,
+        offer_id: Option<String>,
+        _custom_records: Vec<CustomTlvRecord>,
     ) -> Result<(), anyhow::Error> {
         info!("Final callback, payment received!");
         Ok(())
@@ -1,24 +1,32 @@
 use anyhow::Result;
+use grpc_auth::ClientAuthInterceptor;
+pub use grpc_auth::GrpcCredentials;
 use proto::cashu_client::CashuClient;
 use std::time::Duration;
-use tonic::transport::Channel;
+use tonic::{codegen::InterceptedService, transport::Channel};
 use tower::ServiceBuilder;
 
 pub mod proto {
     tonic::include_proto!("cashu_service");
 }
 
-pub async fn get_cashu_client(address: String, keep_alive: bool) -> Result<CashuClient<Channel>> {
-    let channel = tonic::transport::Channel::from_shared(address)?
+pub type CashuInternalClient = CashuClient<InterceptedService<Channel, ClientAuthInterceptor>>;
+
+pub async fn get_cashu_client(
+    address: String,
+    keep_alive: bool,
+    credentials: &GrpcCredentials,
+) -> Result<CashuInternalClient> {
+    let mut endpoint = Channel::from_shared(address)?
         .connect_timeout(Duration::from_secs(10)) // Set connection timeout
-        .keep_alive_while_idle(keep_alive) // Set keep-alive
-        .connect()
-        .await?;
+        .keep_alive_while_idle(keep_alive); // Set keep-alive
+    if let Some(tls_config) = credentials.tls_config()? {
+        endpoint = endpoint.tls_config(tls_config)?;
+    }
+    let channel = endpoint.connect().await?;
 
     let channel = ServiceBuilder::new()
-        // Interceptors can be also be applied as middleware
-        //.layer(tonic::service::interceptor(intercept))
-        //.layer_fn(AuthSvc::new)
+        .layer(tonic::service::interceptor(credentials.interceptor()?))
         .service(channel);
 
     Ok(CashuClient::new(channel))
@@ -9,6 +9,7 @@ use surrealdb::{
 pub use surrealdb;
 
 pub mod config;
+pub mod processed_events;
 
 pub async fn init_db(
     config: SurrealDbConfig,
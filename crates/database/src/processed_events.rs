@@ -0,0 +1,180 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use events::idempotency::{Claim, IdempotencyStore, CLAIM_LEASE};
+use serde::{Deserialize, Serialize};
+use surrealdb::{Connection, Surreal};
+
+const TABLE: &str = "processed_event";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum ProcessedEventStatus {
+    Processing,
+    Processed,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProcessedEvent {
+    consumer: String,
+    event_id: String,
+    status: ProcessedEventStatus,
+    /// Unix timestamp in milliseconds of the last status change
+    updated_at: u64,
+}
+
+impl ProcessedEvent {
+    fn new(consumer: &str, event_id: &str, status: ProcessedEventStatus) -> Result<Self> {
+        Ok(Self {
+            consumer: consumer.to_string(),
+            event_id: event_id.to_string(),
+            status,
+            updated_at: now_millis()?,
+        })
+    }
+}
+
+fn now_millis() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64)
+}
+
+/// Keeps the claims of events in SurrealDB, one record per consumer and event.
+pub struct ProcessedEventStore<C>
+where
+    C: Connection,
+{
+    db: Surreal<C>,
+}
+
+impl<C> ProcessedEventStore<C>
+where
+    C: Connection,
+{
+    pub fn new(db: Surreal<C>) -> Self {
+        Self { db }
+    }
+
+    /// Claims an event whose claim expired, if no other delivery renewed or finished the claim
+    /// since it was read.
+    async fn take_over(&self, consumer: &str, event_id: &str, updated_at: u64) -> Result<Claim> {
+        let mut response = self
+            .db
+            .query(
+                "UPDATE type::thing($tb, $id) SET updated_at = $now \
+                 WHERE status = $status AND updated_at = $updated_at",
+            )
+            .bind(("tb", TABLE))
+            .bind(("id", record_id(consumer, event_id)))
+            .bind(("now", now_millis()?))
+            .bind(("status", ProcessedEventStatus::Processing))
+            .bind(("updated_at", updated_at))
+            .await?;
+        let updated: Vec<ProcessedEvent> = response.take(0)?;
+
+        if updated.is_empty() {
+            Ok(Claim::Processing)
+        } else {
+            Ok(Claim::Claimed)
+        }
+    }
+}
+
+fn record_id(consumer: &str, event_id: &str) -> String {
+    format!("{}:{}", consumer, event_id)
+}
+
+#[async_trait]
+impl<C> IdempotencyStore for ProcessedEventStore<C>
+where
+    C: Connection,
+{
+    async fn claim(&self, consumer: &str, event_id: &str) -> Result<Claim> {
+        // Creating fails if the record exists, so only one delivery gets the claim
+        let created: Result<Option<ProcessedEvent>, _> = self
+            .db
+            .create((TABLE, record_id(consumer, event_id)))
+            .content(ProcessedEvent::new(
+                consumer,
+                event_id,
+                ProcessedEventStatus::Processing,
+            )?)
+            .await;
+
+        let err = match created {
+            Ok(_) => return Ok(Claim::Claimed),
+            Err(err) => err,
+        };
+        let existing: Option<ProcessedEvent> = self
+            .db
+            .select((TABLE, record_id(consumer, event_id)))
+            .await?;
+
+        match existing {
+            Some(event) if event.status == ProcessedEventStatus::Processed => Ok(Claim::Processed),
+            Some(event) if event.updated_at + (CLAIM_LEASE.as_millis() as u64) < now_millis()? => {
+                self.take_over(consumer, event_id, event.updated_at).await
+            }
+            Some(_) => Ok(Claim::Processing),
+            // Creating failed for another reason
+            None => Err(err.into()),
+        }
+    }
+
+    async fn complete(&self, consumer: &str, event_id: &str) -> Result<()> {
+        let _: Option<ProcessedEvent> = self
+            .db
+            .update((TABLE, record_id(consumer, event_id)))
+            .content(ProcessedEvent::new(
+                consumer,
+                event_id,
+                ProcessedEventStatus::Processed,
+            )?)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn release(&self, consumer: &str, event_id: &str) -> Result<()> {
+        let _: Option<ProcessedEvent> = self
+            .db
+            .delete((TABLE, record_id(consumer, event_id)))
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use events::idempotency::{Claim, IdempotencyStore};
+    use surrealdb::{engine::local::Mem, Surreal};
+
+    use super::{record_id, ProcessedEvent, ProcessedEventStatus, ProcessedEventStore, TABLE};
+
+    #[tokio::test]
+    async fn test_stale_claim_is_taken_over() {
+        let db = Surreal::new::<Mem>(()).await.unwrap();
+        db.use_ns("test").await.unwrap();
+        db.use_db("test").await.unwrap();
+        let store = ProcessedEventStore::new(db.clone());
+
+        // A claim left behind long ago by a consumer which stopped
+        let _: Option<ProcessedEvent> = db
+            .create((TABLE, record_id("test", "1")))
+            .content(ProcessedEvent {
+                consumer: "test".to_string(),
+                event_id: "1".to_string(),
+                status: ProcessedEventStatus::Processing,
+                updated_at: 0,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(store.claim("test", "1").await.unwrap(), Claim::Claimed);
+        // The renewed claim holds again
+        assert_eq!(store.claim("test", "1").await.unwrap(), Claim::Processing);
+
+        store.complete("test", "1").await.unwrap();
+        assert_eq!(store.claim("test", "1").await.unwrap(), Claim::Processed);
+    }
+}
@@ -0,0 +1,40 @@
+//! Lists and requeues dead-lettered node events of a consumer queue.
+//!
+//! Usage: `dead_letters list <queue> [limit]` or `dead_letters requeue <queue>`
+
+use anyhow::{bail, Result};
+use dotenv::dotenv;
+use events::event_bus;
+
+const DEFAULT_LIST_LIMIT: usize = 100;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (command, queue) = match (args.first(), args.get(1)) {
+        (Some(command), Some(queue)) => (command.as_str(), queue.as_str()),
+        _ => bail!("Usage: dead_letters list <queue> [limit] | dead_letters requeue <queue>"),
+    };
+
+    let events = event_bus::from_env().await?;
+
+    match command {
+        "list" => {
+            let limit = match args.get(2) {
+                Some(limit) => limit.parse()?,
+                None => DEFAULT_LIST_LIMIT,
+            };
+            let dead_letters = events.dead_letters(queue, limit).await?;
+            println!("{}", serde_json::to_string_pretty(&dead_letters)?);
+        }
+        "requeue" => {
+            let requeued = events.requeue_dead_letters(queue).await?;
+            println!("Requeued {} events to {}", requeued, queue);
+        }
+        _ => bail!("Unknown command \"{}\", expected list or requeue", command),
+    }
+
+    Ok(())
+}
@@ -6,5 +6,9 @@ pub struct RabbitMqConfig {
     pub rabbitmq_port: u16,
     pub rabbitmq_username: String,
     pub rabbitmq_password: String,
-    pub lightning_node_exchange: String
+    pub lightning_node_exchange: String,
+    /// Deliveries of an event before it's dead-lettered, defaults to 5
+    pub event_retry_max_attempts: Option<u32>,
+    /// Delay before the first retry, doubled for every further one, defaults to 1000
+    pub event_retry_initial_delay_ms: Option<u64>,
 }
@@ -0,0 +1,90 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::{
+    config::RabbitMqConfig,
+    lightning_node_events::{
+        ConnectionState, LightningNodeEventHandler, LightningNodeEvents, PaymentReceivedHandler,
+        PaymentReceivedProcessor,
+    },
+    memory::InMemoryEventBus,
+    messages::EventEnvelope,
+    retry::{DeadLetter, RetryPolicy},
+};
+
+/// Delivers node events from the publisher to every subscribed queue.
+///
+/// Each queue gets every event, an event is done once the queue's handler succeeded. Failed
+/// events are retried with a growing delay and dead-lettered after the last attempt.
+#[async_trait]
+pub trait EventBus: Send + Sync {
+    /// Publishes the event, returns once the bus took it over.
+    async fn publish(&self, envelope: EventEnvelope, routing_key: &str) -> Result<()>;
+
+    /// Handles the events of the queue, runs forever.
+    async fn subscribe(
+        &self,
+        queue: &str,
+        handler: Arc<dyn LightningNodeEventHandler + Send + Sync>,
+    ) -> Result<()>;
+
+    async fn subscribe_received_payments(
+        &self,
+        queue: &str,
+        callback: Arc<dyn PaymentReceivedProcessor + Send + Sync>,
+    ) -> Result<()> {
+        self.subscribe(queue, Arc::new(PaymentReceivedHandler::new(callback)))
+            .await
+    }
+
+    /// Returns up to `limit` dead-lettered events of the queue, leaving them in place.
+    async fn dead_letters(&self, queue: &str, limit: usize) -> Result<Vec<DeadLetter>>;
+
+    /// Moves the dead-lettered events of the queue back to it, returns their number.
+    async fn requeue_dead_letters(&self, queue: &str) -> Result<u64>;
+
+    /// States of the connections the bus uses, keyed by their purpose.
+    fn connection_states(&self) -> HashMap<String, ConnectionState>;
+
+    /// Whether all used connections are up, for health checks.
+    fn is_connected(&self) -> bool {
+        self.connection_states()
+            .values()
+            .all(|state| *state == ConnectionState::Connected)
+    }
+}
+
+#[derive(Deserialize)]
+struct EventBusConfig {
+    /// "rabbitmq" or "memory", defaults to rabbitmq
+    event_bus: Option<String>,
+    event_retry_max_attempts: Option<u32>,
+    event_retry_initial_delay_ms: Option<u64>,
+}
+
+/// Builds the event bus selected by `EVENT_BUS`.
+///
+/// The in-memory bus only connects publishers and subscribers of the same process.
+pub async fn from_env() -> Result<Arc<dyn EventBus>> {
+    let config = envy::from_env::<EventBusConfig>()?;
+
+    match config.event_bus.as_deref() {
+        Some("memory") => Ok(Arc::new(InMemoryEventBus::with_retry_policy(
+            RetryPolicy::from_settings(
+                config.event_retry_max_attempts,
+                config.event_retry_initial_delay_ms,
+            ),
+        ))),
+        None | Some("rabbitmq") => {
+            let rabbitmq_config = envy::from_env::<RabbitMqConfig>()?;
+            Ok(Arc::new(LightningNodeEvents::new(rabbitmq_config).await?))
+        }
+        Some(event_bus) => anyhow::bail!(
+            "Unknown event bus \"{}\", expected rabbitmq or memory",
+            event_bus
+        ),
+    }
+}
@@ -0,0 +1,20 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{http::StatusCode, Extension, Json};
+
+use crate::{event_bus::EventBus, lightning_node_events::ConnectionState};
+
+/// Axum handler reporting the event bus connections, unavailable while any of them is down.
+///
+/// Expects the bus as an `Extension<Arc<dyn EventBus>>`.
+pub async fn health(
+    events: Extension<Arc<dyn EventBus>>,
+) -> (StatusCode, Json<HashMap<String, ConnectionState>>) {
+    let status = if events.is_connected() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(events.connection_states()))
+}
@@ -0,0 +1,217 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use log::info;
+
+use crate::{
+    lightning_node_events::LightningNodeEventHandler,
+    messages::{EventEnvelope, LightningNodeEvent},
+};
+
+/// How long a claim holds without being completed or released. A claim left behind by a
+/// consumer which stopped is taken over by the next delivery once it's older, so handling an
+/// event has to take less time.
+pub const CLAIM_LEASE: Duration = Duration::from_secs(300);
+
+/// State of an event when a consumer claims it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Claim {
+    /// The caller claimed the event and has to process it
+    Claimed,
+    /// Another delivery claimed the event within the last `CLAIM_LEASE` and didn't finish it
+    Processing,
+    /// The event was processed already
+    Processed,
+}
+
+/// Records which events a consumer processes, keyed by the event id.
+#[async_trait]
+pub trait IdempotencyStore {
+    /// Atomically records that the consumer starts processing the event, only one caller gets
+    /// `Claim::Claimed` for an event. A claim older than `CLAIM_LEASE` is taken over the same
+    /// way.
+    async fn claim(&self, consumer: &str, event_id: &str) -> Result<Claim>;
+
+    /// Records a claimed event as processed.
+    async fn complete(&self, consumer: &str, event_id: &str) -> Result<()>;
+
+    /// Drops the claim of an event which failed, so it can be claimed again.
+    async fn release(&self, consumer: &str, event_id: &str) -> Result<()>;
+}
+
+/// Handler which passes every event to the wrapped handler at most once, even when the bus
+/// delivers it again.
+///
+/// An event is claimed before the wrapped handler runs and released if it fails, so a failed
+/// event is retried. An event whose claim is left behind, because the consumer stopped while
+/// handling it or the store failed, is rejected until the claim is older than `CLAIM_LEASE`
+/// and handled again afterwards, unless the bus dead-letters it first. Legacy events have no id
+/// and are always handled.
+pub struct Deduplicated {
+    consumer: String,
+    handler: Arc<dyn LightningNodeEventHandler + Send + Sync>,
+    store: Arc<dyn IdempotencyStore + Send + Sync>,
+}
+
+impl Deduplicated {
+    /// The consumer name scopes the recorded ids, usually it's the queue name.
+    pub fn new(
+        consumer: &str,
+        handler: Arc<dyn LightningNodeEventHandler + Send + Sync>,
+        store: Arc<dyn IdempotencyStore + Send + Sync>,
+    ) -> Self {
+        Self {
+            consumer: consumer.to_string(),
+            handler,
+            store,
+        }
+    }
+}
+
+#[async_trait]
+impl LightningNodeEventHandler for Deduplicated {
+    async fn handle_envelope(&self, envelope: EventEnvelope) -> Result<()> {
+        if envelope.is_legacy() {
+            return self.handler.handle_envelope(envelope).await;
+        }
+
+        match self.store.claim(&self.consumer, &envelope.id).await? {
+            Claim::Claimed => {}
+            Claim::Processed => {
+                info!(
+                    "Skipping {} event {}, {} processed it already",
+                    envelope.event.event_type(),
+                    envelope.id,
+                    self.consumer
+                );
+                return Ok(());
+            }
+            Claim::Processing => bail!(
+                "{} event {} is claimed by {} without being processed",
+                envelope.event.event_type(),
+                envelope.id,
+                self.consumer
+            ),
+        }
+
+        let event_id = envelope.id.clone();
+        if let Err(err) = self.handler.handle_envelope(envelope).await {
+            if let Err(release_err) = self.store.release(&self.consumer, &event_id).await {
+                return Err(err.context(format!(
+                    "Cannot release event {} of {}: {}",
+                    event_id, self.consumer, release_err
+                )));
+            }
+            return Err(err);
+        }
+
+        self.store.complete(&self.consumer, &event_id).await
+    }
+
+    async fn handle(&self, event: LightningNodeEvent) -> Result<()> {
+        self.handler.handle(event).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use anyhow::{bail, Result};
+    use async_trait::async_trait;
+
+    use crate::{
+        lightning_node_events::LightningNodeEventHandler,
+        messages::{EventEnvelope, LightningNodeEvent},
+    };
+
+    use super::{Claim, Deduplicated, IdempotencyStore};
+
+    #[derive(Default)]
+    struct MemoryStore {
+        claims: Mutex<HashMap<String, Claim>>,
+    }
+
+    #[async_trait]
+    impl IdempotencyStore for MemoryStore {
+        async fn claim(&self, _consumer: &str, event_id: &str) -> Result<Claim> {
+            let mut claims = self.claims.lock().unwrap();
+            match claims.get(event_id) {
+                Some(Claim::Processed) => Ok(Claim::Processed),
+                Some(_) => Ok(Claim::Processing),
+                None => {
+                    claims.insert(event_id.to_string(), Claim::Processing);
+                    Ok(Claim::Claimed)
+                }
+            }
+        }
+
+        async fn complete(&self, _consumer: &str, event_id: &str) -> Result<()> {
+            let mut claims = self.claims.lock().unwrap();
+            claims.insert(event_id.to_string(), Claim::Processed);
+            Ok(())
+        }
+
+        async fn release(&self, _consumer: &str, event_id: &str) -> Result<()> {
+            self.claims.lock().unwrap().remove(event_id);
+            Ok(())
+        }
+    }
+
+    /// Fails the first call, succeeds afterwards.
+    #[derive(Default)]
+    struct FlakyHandler {
+        calls: AtomicU32,
+    }
+
+    #[async_trait]
+    impl LightningNodeEventHandler for FlakyHandler {
+        async fn payment_failed(
+            &self,
+            _payment_hash: String,
+            _reason: Option<String>,
+        ) -> Result<()> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                bail!("handler failed")
+            }
+            Ok(())
+        }
+    }
+
+    fn envelope(id: &str) -> EventEnvelope {
+        EventEnvelope::new(
+            id.to_string(),
+            "test".to_string(),
+            0,
+            LightningNodeEvent::PaymentFailed {
+                payment_hash: "hash".to_string(),
+                reason: None,
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_handles_event_once() {
+        let handler = Arc::new(FlakyHandler::default());
+        let store = Arc::new(MemoryStore::default());
+        let deduplicated = Deduplicated::new("test", handler.clone(), store.clone());
+
+        // A failed event is released and handled again
+        assert!(deduplicated.handle_envelope(envelope("1")).await.is_err());
+        deduplicated.handle_envelope(envelope("1")).await.unwrap();
+        deduplicated.handle_envelope(envelope("1")).await.unwrap();
+        assert_eq!(handler.calls.load(Ordering::SeqCst), 2);
+
+        // An event claimed by another delivery is rejected
+        assert_eq!(store.claim("test", "2").await.unwrap(), Claim::Claimed);
+        assert!(deduplicated.handle_envelope(envelope("2")).await.is_err());
+        assert_eq!(handler.calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -1,4 +1,9 @@
 pub mod config;
+pub mod event_bus;
+pub mod health;
+pub mod idempotency;
 pub mod messages;
 pub mod lightning_node_events;
-pub mod rabbitmq;
\ No newline at end of file
+pub mod memory;
+pub mod rabbitmq;
+pub mod retry;
\ No newline at end of file
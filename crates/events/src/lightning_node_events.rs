@@ -1,153 +1,547 @@
 use amqprs::channel::{
-    BasicAckArguments, BasicConsumeArguments, Channel, QueueBindArguments, QueueDeclareArguments,
+    BasicAckArguments, BasicConsumeArguments, BasicQosArguments, Channel, QueueBindArguments,
+    QueueDeclareArguments,
 };
 use amqprs::consumer::AsyncConsumer;
 use amqprs::Deliver;
 use amqprs::{channel::BasicPublishArguments, connection::Connection, BasicProperties};
 use anyhow::{bail, Result};
 use async_trait::async_trait;
-use log::warn;
-use tokio::sync::Notify;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::sleep;
 
-use crate::messages::LightningNodeEvent;
+use crate::event_bus::EventBus;
+use crate::messages::{CustomTlvRecord, EventEnvelope, LightningNodeEvent};
+use crate::rabbitmq::ConfirmedPublisher;
+use crate::retry::{self, DeadLetter, RetryQueues};
 use crate::{config::RabbitMqConfig, rabbitmq};
 
-/// Publisher sends events to subscribers (listeners).
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+const CONNECTION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const PUBLISHER_CONNECTION: &str = "publisher";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ConnectionState {
+    /// Not connected yet
+    Connecting,
+    Connected,
+    /// The connection was lost and is being reopened
+    Reconnecting,
+}
+
+/// RabbitMQ event bus, publisher sends events to subscribers (listeners).
+///
+/// Publishing and every subscription use their own connection, which is opened on first use
+/// and reopened whenever the broker closes it.
 pub struct LightningNodeEvents {
     pub config: RabbitMqConfig,
-    pub connection: Connection,
-    pub channel: Channel,
+    publisher: Mutex<Option<Publisher>>,
+    states: std::sync::Mutex<HashMap<String, ConnectionState>>,
 }
 
-impl LightningNodeEvents {
-    pub async fn new(config: RabbitMqConfig) -> Result<Self> {
-        let connection = rabbitmq::get_rabbitmq_connection(
-            &config.rabbitmq_host,
-            config.rabbitmq_port,
-            &config.rabbitmq_username,
-            &config.rabbitmq_password,
-        )
-        .await?;
-
-        let channel = connection.open_channel(None).await.unwrap();
+struct Publisher {
+    connection: Connection,
+    channel: Channel,
+    confirmed: ConfirmedPublisher,
+}
+
+impl Publisher {
+    async fn open(config: &RabbitMqConfig) -> Result<Self> {
+        let connection = rabbitmq::connect(config).await?;
+        let channel = connection.open_channel(None).await?;
         rabbitmq::ensure_exchange_created(&channel, &config.lightning_node_exchange, "fanout")
             .await?;
+        let confirmed = ConfirmedPublisher::enable(&channel).await?;
 
         Ok(Self {
-            config,
             connection,
             channel,
+            confirmed,
         })
     }
 
-    pub async fn notify(&self, event: LightningNodeEvent, routing_key: &str) {
-        let args = BasicPublishArguments::new(&self.config.lightning_node_exchange, routing_key);
+    fn is_open(&self) -> bool {
+        self.connection.is_open() && self.channel.is_open()
+    }
+}
+
+impl LightningNodeEvents {
+    /// Doesn't connect yet, so a broker which is down doesn't keep the service from starting.
+    pub async fn new(config: RabbitMqConfig) -> Result<Self> {
+        Ok(Self {
+            config,
+            publisher: Mutex::new(None),
+            states: std::sync::Mutex::new(HashMap::new()),
+        })
+    }
 
-        let content = serde_json::json!(event).to_string().into_bytes();
+    fn set_state(&self, connection: &str, state: ConnectionState) {
+        self.states
+            .lock()
+            .unwrap()
+            .insert(connection.to_string(), state);
+    }
 
-        self.channel
-            .basic_publish(BasicProperties::default(), content, args)
+    /// Opens a connection, declares the queues and starts the consumer.
+    async fn consume(
+        &self,
+        queue: &str,
+        handler: Arc<dyn LightningNodeEventHandler + Send + Sync>,
+        retry_queues: Arc<RetryQueues>,
+    ) -> Result<(Connection, Channel)> {
+        let connection = rabbitmq::connect(&self.config).await?;
+        match self
+            .start_consumer(&connection, queue, handler, retry_queues)
             .await
-            .unwrap();
+        {
+            Ok(channel) => Ok((connection, channel)),
+            Err(err) => {
+                connection.close().await.ok();
+                Err(err)
+            }
+        }
     }
 
-    pub async fn subscribe_received_payments<F>(&self, queue: &str, callback: F) -> Result<()>
-    where
-        F: PaymentReceivedProcessor + Send + Sync + 'static,
-    {
+    async fn start_consumer(
+        &self,
+        connection: &Connection,
+        queue: &str,
+        handler: Arc<dyn LightningNodeEventHandler + Send + Sync>,
+        retry_queues: Arc<RetryQueues>,
+    ) -> Result<Channel> {
+        let channel = connection.open_channel(None).await?;
+        rabbitmq::ensure_exchange_created(&channel, &self.config.lightning_node_exchange, "fanout")
+            .await?;
+        retry_queues.declare(&channel).await?;
+
         // declare a queue
-        let (queue_name, _, _) = self
-            .channel
+        let (queue_name, _, _) = match channel
             .queue_declare(QueueDeclareArguments::durable_client_named(queue))
-            .await
-            .unwrap()
-            .unwrap();
+            .await?
+        {
+            Some(declared) => declared,
+            None => bail!("Queue {} was not declared", queue),
+        };
 
         // bind the queue to exchange
         let rounting_key = "*";
         let exchange_name = &self.config.lightning_node_exchange; //"walletka.lightning-node";
-        self.channel
+        channel
             .queue_bind(QueueBindArguments::new(
                 &queue_name,
                 exchange_name,
                 rounting_key,
             ))
-            .await
-            .unwrap();
+            .await?;
 
-        if !self.channel.is_connection_open() {
-            bail!("Connection is closed");
-        }
+        // One event at a time, so a retried event doesn't overtake later ones more than needed
+        channel
+            .basic_qos(BasicQosArguments::new(0, 1, false))
+            .await?;
+
+        // Failed events are published to the retry queues before they are acked
+        let publisher = ConfirmedPublisher::enable(&channel).await?;
 
         let args = BasicConsumeArguments::new(queue, "");
 
-        self.channel
-            .basic_consume(WalletkaNodePaymentsConsumer { callback }, args)
+        channel
+            .basic_consume(
+                LightningNodeEventsConsumer {
+                    handler,
+                    retry_queues,
+                    publisher,
+                },
+                args,
+            )
+            .await?;
+
+        Ok(channel)
+    }
+}
+
+#[async_trait]
+impl EventBus for LightningNodeEvents {
+    /// Publishes the event and waits until the broker confirms it.
+    ///
+    /// Reopens the connection first if it was closed, callers retry on errors.
+    async fn publish(&self, envelope: EventEnvelope, routing_key: &str) -> Result<()> {
+        let args = BasicPublishArguments::new(&self.config.lightning_node_exchange, routing_key);
+
+        let content = serde_json::json!(envelope).to_string().into_bytes();
+
+        // Publishes are confirmed in order, hold the lock until confirmed
+        let mut publisher = self.publisher.lock().await;
+        let publisher = match publisher.take() {
+            Some(open) if open.is_open() => publisher.insert(open),
+            previous => {
+                let state = match previous {
+                    Some(_) => ConnectionState::Reconnecting,
+                    None => ConnectionState::Connecting,
+                };
+                self.set_state(PUBLISHER_CONNECTION, state);
+
+                let opened = Publisher::open(&self.config).await?;
+                self.set_state(PUBLISHER_CONNECTION, ConnectionState::Connected);
+                publisher.insert(opened)
+            }
+        };
+
+        publisher
+            .confirmed
+            .publish(
+                &publisher.channel,
+                BasicProperties::default().with_delivery_mode(2).finish(),
+                content,
+                args,
+            )
             .await
-            .unwrap();
+    }
+
+    /// Consumes all node events from the queue and dispatches them to the handler.
+    ///
+    /// An event is acked once the handler succeeded, events of kinds the handler doesn't
+    /// override are acked right away. Failed events are delivered again with a growing delay,
+    /// and dead-lettered once they failed `event_retry_max_attempts` times. Events which can't
+    /// be parsed are dead-lettered right away.
+    ///
+    /// Runs forever, when the connection or channel closes the queues are declared again on
+    /// a new connection and consuming resumes.
+    async fn subscribe(
+        &self,
+        queue: &str,
+        handler: Arc<dyn LightningNodeEventHandler + Send + Sync>,
+    ) -> Result<()> {
+        let retry_queues = Arc::new(RetryQueues::new(&self.config, queue));
+        let mut reconnect_delay = RECONNECT_MIN_DELAY;
+        self.set_state(queue, ConnectionState::Connecting);
+
+        loop {
+            match self
+                .consume(queue, handler.clone(), retry_queues.clone())
+                .await
+            {
+                Ok((connection, channel)) => {
+                    info!("Consuming events from {}", queue);
+                    self.set_state(queue, ConnectionState::Connected);
+                    reconnect_delay = RECONNECT_MIN_DELAY;
+
+                    while connection.is_open() && channel.is_open() {
+                        sleep(CONNECTION_CHECK_INTERVAL).await;
+                    }
+                    warn!("Connection of {} was closed, reconnecting", queue);
+                    connection.close().await.ok();
+                }
+                Err(err) => {
+                    warn!(
+                        "Cannot consume events from {}, retrying in {:?}: {}",
+                        queue, reconnect_delay, err
+                    );
+                    sleep(reconnect_delay).await;
+                    reconnect_delay = (reconnect_delay * 2).min(RECONNECT_MAX_DELAY);
+                }
+            }
+
+            self.set_state(queue, ConnectionState::Reconnecting);
+        }
+    }
 
-        let guard = Notify::new();
-        guard.notified().await;
+    async fn dead_letters(&self, queue: &str, limit: usize) -> Result<Vec<DeadLetter>> {
+        let connection = rabbitmq::connect(&self.config).await?;
+        let channel = connection.open_channel(None).await?;
+        let dead_letters = retry::list_dead_letters(&channel, queue, limit).await;
+        connection.close().await.ok();
 
+        dead_letters
+    }
+
+    async fn requeue_dead_letters(&self, queue: &str) -> Result<u64> {
+        let connection = rabbitmq::connect(&self.config).await?;
+        let channel = connection.open_channel(None).await?;
+        let requeued = retry::requeue_dead_letters(&channel, queue).await;
+        connection.close().await.ok();
+
+        requeued
+    }
+
+    /// States of the publisher connection and of the connection of every subscribed queue.
+    ///
+    /// Only connections which were used are listed.
+    fn connection_states(&self) -> HashMap<String, ConnectionState> {
+        self.states.lock().unwrap().clone()
+    }
+}
+
+/// Handles node events, every method defaults to ignoring its event kind.
+#[async_trait]
+pub trait LightningNodeEventHandler {
+    async fn payment_successful(
+        &self,
+        _payment_hash: String,
+        _fee_paid_msat: Option<u64>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn payment_failed(&self, _payment_hash: String, _reason: Option<String>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn payment_received(
+        &self,
+        _payment_hash: String,
+        _amount_msat: u64,
+        _offer_id: Option<String>,
+        _custom_records: Vec<CustomTlvRecord>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn payment_claimable(
+        &self,
+        _payment_hash: String,
+        _claimable_amount_msat: u64,
+        _claim_deadline: Option<u32>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn payment_forwarded(
+        &self,
+        _prev_channel_id: String,
+        _next_channel_id: String,
+        _total_fee_earned_msat: Option<u64>,
+        _claim_from_onchain_tx: bool,
+        _outbound_amount_forwarded_msat: Option<u64>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn channel_pending(
+        &self,
+        _channel_id: String,
+        _user_channel_id: String,
+        _former_temporary_channel_id: String,
+        _counterparty_node_id: String,
+        _funding_txo: String,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn channel_ready(
+        &self,
+        _channel_id: String,
+        _user_channel_id: String,
+        _counterparty_node_id: Option<String>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn channel_closed(
+        &self,
+        _channel_id: String,
+        _user_channel_id: String,
+        _counterparty_node_id: Option<String>,
+        _reason: Option<String>,
+    ) -> Result<()> {
         Ok(())
     }
+
+    /// Handles the event as received from the bus, override to use its id or metadata.
+    async fn handle_envelope(&self, envelope: EventEnvelope) -> Result<()> {
+        self.handle(envelope.event).await
+    }
+
+    /// Dispatches the event to the method of its kind.
+    async fn handle(&self, event: LightningNodeEvent) -> Result<()> {
+        match event {
+            LightningNodeEvent::PaymentSuccessful {
+                payment_hash,
+                fee_paid_msat,
+            } => self.payment_successful(payment_hash, fee_paid_msat).await,
+            LightningNodeEvent::PaymentFailed {
+                payment_hash,
+                reason,
+            } => self.payment_failed(payment_hash, reason).await,
+            LightningNodeEvent::PaymentReceived {
+                payment_hash,
+                amount_msat,
+                offer_id,
+                custom_records,
+            } => {
+                self.payment_received(payment_hash, amount_msat, offer_id, custom_records)
+                    .await
+            }
+            LightningNodeEvent::PaymentClaimable {
+                payment_hash,
+                claimable_amount_msat,
+                claim_deadline,
+            } => {
+                self.payment_claimable(payment_hash, claimable_amount_msat, claim_deadline)
+                    .await
+            }
+            LightningNodeEvent::PaymentForwarded {
+                prev_channel_id,
+                next_channel_id,
+                total_fee_earned_msat,
+                claim_from_onchain_tx,
+                outbound_amount_forwarded_msat,
+            } => {
+                self.payment_forwarded(
+                    prev_channel_id,
+                    next_channel_id,
+                    total_fee_earned_msat,
+                    claim_from_onchain_tx,
+                    outbound_amount_forwarded_msat,
+                )
+                .await
+            }
+            LightningNodeEvent::ChannelPending {
+                channel_id,
+                user_channel_id,
+                former_temporary_channel_id,
+                counterparty_node_id,
+                funding_txo,
+            } => {
+                self.channel_pending(
+                    channel_id,
+                    user_channel_id,
+                    former_temporary_channel_id,
+                    counterparty_node_id,
+                    funding_txo,
+                )
+                .await
+            }
+            LightningNodeEvent::ChannelReady {
+                channel_id,
+                user_channel_id,
+                counterparty_node_id,
+            } => {
+                self.channel_ready(channel_id, user_channel_id, counterparty_node_id)
+                    .await
+            }
+            LightningNodeEvent::ChannelClosed {
+                channel_id,
+                user_channel_id,
+                counterparty_node_id,
+                reason,
+            } => {
+                self.channel_closed(channel_id, user_channel_id, counterparty_node_id, reason)
+                    .await
+            }
+        }
+    }
 }
 
 #[async_trait]
 pub trait PaymentReceivedProcessor {
-    async fn payment_received_callback(&self, payment_hash: String, amount_msat: u64)
-        -> Result<()>;
+    async fn payment_received_callback(
+        &self,
+        payment_hash: String,
+        amount_msat: u64,
+        offer_id: Option<String>,
+        custom_records: Vec<CustomTlvRecord>,
+    ) -> Result<()>;
+}
+
+/// Adapts a `PaymentReceivedProcessor` to a handler ignoring all other events.
+pub struct PaymentReceivedHandler {
+    callback: Arc<dyn PaymentReceivedProcessor + Send + Sync>,
+}
+
+impl PaymentReceivedHandler {
+    pub fn new(callback: Arc<dyn PaymentReceivedProcessor + Send + Sync>) -> Self {
+        Self { callback }
+    }
+}
+
+#[async_trait]
+impl LightningNodeEventHandler for PaymentReceivedHandler {
+    async fn payment_received(
+        &self,
+        payment_hash: String,
+        amount_msat: u64,
+        offer_id: Option<String>,
+        custom_records: Vec<CustomTlvRecord>,
+    ) -> Result<()> {
+        self.callback
+            .payment_received_callback(payment_hash, amount_msat, offer_id, custom_records)
+            .await
+    }
 }
 
-pub struct WalletkaNodePaymentsConsumer<F>
+pub struct LightningNodeEventsConsumer<H>
 where
-    F: PaymentReceivedProcessor + Send + Sync + 'static,
+    H: LightningNodeEventHandler + Send + Sync + ?Sized + 'static,
 {
-    callback: F,
+    handler: Arc<H>,
+    retry_queues: Arc<RetryQueues>,
+    publisher: ConfirmedPublisher,
 }
 
 #[async_trait]
-impl<F> AsyncConsumer for WalletkaNodePaymentsConsumer<F>
+impl<H> AsyncConsumer for LightningNodeEventsConsumer<H>
 where
-    F: PaymentReceivedProcessor + Send + Sync + 'static,
+    H: LightningNodeEventHandler + Send + Sync + ?Sized + 'static,
 {
     async fn consume(
         &mut self,
         channel: &amqprs::channel::Channel,
         deliver: Deliver,
-        _basic_properties: BasicProperties,
+        basic_properties: BasicProperties,
         content: Vec<u8>,
     ) {
-        let content = String::from_utf8(content).unwrap();
-
-        let payment: Result<crate::lightning_node_events::LightningNodeEvent, serde_json::Error> =
-            serde_json::from_str(&content);
+        let envelope = EventEnvelope::decode(&content);
 
         let ack_args = BasicAckArguments::new(deliver.delivery_tag(), false);
-        match payment {
-            Ok(payment) => match payment {
-                LightningNodeEvent::PaymentReceived {
-                    payment_hash,
-                    amount_msat,
-                } => {
-                    match self
-                        .callback
-                        .payment_received_callback(payment_hash, amount_msat)
-                        .await
-                    {
-                        Ok(_) => {
-                            channel.basic_ack(ack_args).await.unwrap();
+        let result = match envelope {
+            Ok(envelope) => {
+                let event_type = envelope.event.event_type();
+                match self.handler.handle_envelope(envelope).await {
+                    Ok(_) => {
+                        // An unacked event is delivered again once the channel is reopened
+                        if let Err(err) = channel.basic_ack(ack_args).await {
+                            error!("Cannot ack {} event: {}", event_type, err);
                         }
-                        Err(_) => {}
+                        Ok(())
+                    }
+                    Err(err) => {
+                        warn!("Cannot handle {} event, retrying: {}", event_type, err);
+                        self.retry_queues
+                            .retry_or_dead_letter(
+                                channel,
+                                &mut self.publisher,
+                                deliver.delivery_tag(),
+                                &basic_properties,
+                                content,
+                                &err.to_string(),
+                            )
+                            .await
                     }
                 }
-                _ => {
-                    channel.basic_ack(ack_args).await.unwrap();
-                }
-            },
+            }
             Err(err) => {
-                warn!("Error: {}", err)
+                warn!("Cannot parse event, dead-lettering it: {}", err);
+                self.retry_queues
+                    .dead_letter(
+                        channel,
+                        &mut self.publisher,
+                        deliver.delivery_tag(),
+                        &basic_properties,
+                        content,
+                        &err.to_string(),
+                    )
+                    .await
             }
+        };
+
+        if let Err(err) = result {
+            error!("Cannot hand over failed event, it's requeued: {}", err);
         }
     }
 }
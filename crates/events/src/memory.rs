@@ -0,0 +1,294 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use log::warn;
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    time::sleep,
+};
+
+use crate::{
+    event_bus::EventBus,
+    lightning_node_events::{ConnectionState, LightningNodeEventHandler},
+    messages::EventEnvelope,
+    retry::{DeadLetter, RetryPolicy},
+};
+
+/// In-process event bus for tests and single-process setups, no broker needed.
+///
+/// Behaves like the RabbitMQ bus: every queue gets each event published after it was first
+/// subscribed, keeps events while no subscriber runs, handles one event at a time and retries
+/// and dead-letters failed events with the same policy. Nothing survives a restart.
+pub struct InMemoryEventBus {
+    policy: RetryPolicy,
+    queues: Mutex<HashMap<String, MemoryQueue>>,
+}
+
+struct MemoryQueue {
+    sender: UnboundedSender<Delivery>,
+    receiver: Arc<tokio::sync::Mutex<UnboundedReceiver<Delivery>>>,
+    dead_letters: Arc<Mutex<Vec<DeadLetter>>>,
+}
+
+struct Delivery {
+    content: String,
+    /// Failed deliveries before this one
+    attempts: u32,
+}
+
+impl InMemoryEventBus {
+    pub fn new() -> Self {
+        Self::with_retry_policy(RetryPolicy::default())
+    }
+
+    pub fn with_retry_policy(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            queues: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn queue<T>(&self, queue: &str, f: impl FnOnce(&MemoryQueue) -> T) -> T {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.entry(queue.to_string()).or_insert_with(|| {
+            let (sender, receiver) = unbounded_channel();
+            MemoryQueue {
+                sender,
+                receiver: Arc::new(tokio::sync::Mutex::new(receiver)),
+                dead_letters: Arc::new(Mutex::new(vec![])),
+            }
+        });
+
+        f(queue)
+    }
+}
+
+impl Default for InMemoryEventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl EventBus for InMemoryEventBus {
+    async fn publish(&self, envelope: EventEnvelope, _routing_key: &str) -> Result<()> {
+        let content = serde_json::json!(envelope).to_string();
+
+        for queue in self.queues.lock().unwrap().values() {
+            // The queue holds its own receiver, sending can't fail
+            queue
+                .sender
+                .send(Delivery {
+                    content: content.clone(),
+                    attempts: 0,
+                })
+                .ok();
+        }
+
+        Ok(())
+    }
+
+    async fn subscribe(
+        &self,
+        queue: &str,
+        handler: Arc<dyn LightningNodeEventHandler + Send + Sync>,
+    ) -> Result<()> {
+        let (sender, receiver, dead_letters) = self.queue(queue, |queue| {
+            (
+                queue.sender.clone(),
+                queue.receiver.clone(),
+                queue.dead_letters.clone(),
+            )
+        });
+
+        loop {
+            let delivery = match receiver.lock().await.recv().await {
+                Some(delivery) => delivery,
+                None => return Ok(()),
+            };
+            let attempts = delivery.attempts + 1;
+
+            let error = match EventEnvelope::decode(delivery.content.as_bytes()) {
+                Ok(envelope) => {
+                    let event_type = envelope.event.event_type();
+                    match handler.handle_envelope(envelope).await {
+                        Ok(_) => continue,
+                        Err(err) if attempts < self.policy.max_attempts => {
+                            warn!("Cannot handle {} event, retrying: {}", event_type, err);
+                            let sender = sender.clone();
+                            let delay = self.policy.delay(attempts);
+                            tokio::spawn(async move {
+                                sleep(delay).await;
+                                sender
+                                    .send(Delivery {
+                                        content: delivery.content,
+                                        attempts,
+                                    })
+                                    .ok();
+                            });
+                            continue;
+                        }
+                        Err(err) => {
+                            warn!(
+                                "Cannot handle {} event, dead-lettering it: {}",
+                                event_type, err
+                            );
+                            err.to_string()
+                        }
+                    }
+                }
+                Err(err) => {
+                    warn!("Cannot parse event, dead-lettering it: {}", err);
+                    err.to_string()
+                }
+            };
+
+            dead_letters.lock().unwrap().push(DeadLetter {
+                content: delivery.content,
+                attempts,
+                error: Some(error),
+            });
+        }
+    }
+
+    async fn dead_letters(&self, queue: &str, limit: usize) -> Result<Vec<DeadLetter>> {
+        Ok(self.queue(queue, |queue| {
+            let dead_letters = queue.dead_letters.lock().unwrap();
+            dead_letters.iter().take(limit).cloned().collect()
+        }))
+    }
+
+    async fn requeue_dead_letters(&self, queue: &str) -> Result<u64> {
+        Ok(self.queue(queue, |queue| {
+            let dead_letters: Vec<DeadLetter> =
+                queue.dead_letters.lock().unwrap().drain(..).collect();
+            let requeued = dead_letters.len() as u64;
+            for dead_letter in dead_letters {
+                queue
+                    .sender
+                    .send(Delivery {
+                        content: dead_letter.content,
+                        attempts: 0,
+                    })
+                    .ok();
+            }
+
+            requeued
+        }))
+    }
+
+    /// No connections are used.
+    fn connection_states(&self) -> HashMap<String, ConnectionState> {
+        HashMap::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use anyhow::{bail, Result};
+    use async_trait::async_trait;
+    use tokio::{
+        sync::mpsc::{unbounded_channel, UnboundedSender},
+        task::yield_now,
+        time::timeout,
+    };
+
+    use crate::{
+        event_bus::EventBus,
+        lightning_node_events::LightningNodeEventHandler,
+        messages::{EventEnvelope, LightningNodeEvent},
+        retry::RetryPolicy,
+    };
+
+    use super::InMemoryEventBus;
+
+    const TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Fails every event, reporting the number of each call.
+    struct FailingHandler {
+        calls: AtomicU32,
+        sender: UnboundedSender<u32>,
+    }
+
+    #[async_trait]
+    impl LightningNodeEventHandler for FailingHandler {
+        async fn payment_failed(
+            &self,
+            _payment_hash: String,
+            _reason: Option<String>,
+        ) -> Result<()> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            self.sender.send(call).ok();
+            bail!("handler failed")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_and_dead_letter() {
+        let events = Arc::new(InMemoryEventBus::with_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            initial_delay: Duration::from_millis(1),
+        }));
+        let (sender, mut calls) = unbounded_channel();
+        let handler = Arc::new(FailingHandler {
+            calls: AtomicU32::new(0),
+            sender,
+        });
+
+        // Reading the dead letters creates the queue, so it keeps the event until the
+        // subscriber runs
+        assert!(events.dead_letters("test", 10).await.unwrap().is_empty());
+        let subscriber = events.clone();
+        tokio::spawn(async move { subscriber.subscribe("test", handler).await });
+
+        events
+            .publish(
+                EventEnvelope::new(
+                    "id".to_string(),
+                    "test".to_string(),
+                    0,
+                    LightningNodeEvent::PaymentFailed {
+                        payment_hash: "hash".to_string(),
+                        reason: None,
+                    },
+                ),
+                "test",
+            )
+            .await
+            .unwrap();
+
+        for attempt in 1..=3 {
+            let call = timeout(TEST_TIMEOUT, calls.recv()).await.unwrap();
+            assert_eq!(call, Some(attempt));
+        }
+        let dead_letters = timeout(TEST_TIMEOUT, async {
+            loop {
+                let dead_letters = events.dead_letters("test", 10).await.unwrap();
+                if !dead_letters.is_empty() {
+                    return dead_letters;
+                }
+                yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].attempts, 3);
+        assert!(calls.try_recv().is_err());
+
+        assert_eq!(events.requeue_dead_letters("test").await.unwrap(), 1);
+        assert!(events.dead_letters("test", 10).await.unwrap().is_empty());
+    }
+}
@@ -6,11 +6,15 @@ pub enum LightningNodeEvent {
     PaymentSuccessful {
         /// The hash of the payment.
         payment_hash: String,
+        /// The total fee which was spent at intermediate hops in this payment.
+        fee_paid_msat: Option<u64>,
     },
     /// A sent payment has failed.
     PaymentFailed {
         /// The hash of the payment.
         payment_hash: String,
+        /// The reason why the payment failed.
+        reason: Option<String>,
     },
     /// A payment has been received.
     PaymentReceived {
@@ -18,6 +22,33 @@ pub enum LightningNodeEvent {
         payment_hash: String,
         /// The value, in thousandths of a satoshi, that has been received.
         amount_msat: u64,
+        /// The id of the BOLT12 offer the payment was made to.
+        offer_id: Option<String>,
+        /// Custom TLV records the sender attached to the payment.
+        #[serde(default)]
+        custom_records: Vec<CustomTlvRecord>,
+    },
+    /// A payment for a hold invoice has arrived and waits to be settled or cancelled.
+    PaymentClaimable {
+        /// The hash of the payment.
+        payment_hash: String,
+        /// The value, in thousandths of a satoshi, that is claimable.
+        claimable_amount_msat: u64,
+        /// The block height at which the payment has to be settled or cancelled at the latest.
+        claim_deadline: Option<u32>,
+    },
+    /// A payment has been forwarded through one of our channels.
+    PaymentForwarded {
+        /// The `channel_id` of the incoming channel.
+        prev_channel_id: String,
+        /// The `channel_id` of the outgoing channel.
+        next_channel_id: String,
+        /// The total fee, in milli-satoshis, which was earned as a result of the payment.
+        total_fee_earned_msat: Option<u64>,
+        /// If this is `true`, the forwarded HTLC was claimed by our counterparty via an on-chain transaction.
+        claim_from_onchain_tx: bool,
+        /// The final amount forwarded, in milli-satoshis, after the fee is deducted.
+        outbound_amount_forwarded_msat: Option<u64>,
     },
     /// A channel has been created and is pending confirmation on-chain.
     ChannelPending {
@@ -53,5 +84,129 @@ pub enum LightningNodeEvent {
         ///
         /// This will be `None` for events serialized by LDK Node XXX TODO and prior.
         counterparty_node_id: Option<String>,
+        /// The reason why the channel was closed.
+        reason: Option<String>,
     },
 }
+
+/// A custom TLV record of a payment onion.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CustomTlvRecord {
+    /// The type number of the record, at least 2^16.
+    pub tlv_type: u64,
+    /// The hex encoded value of the record.
+    pub value: String,
+}
+
+impl LightningNodeEvent {
+    /// Name of the variant, used to filter events by kind.
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            LightningNodeEvent::PaymentSuccessful { .. } => "PaymentSuccessful",
+            LightningNodeEvent::PaymentFailed { .. } => "PaymentFailed",
+            LightningNodeEvent::PaymentReceived { .. } => "PaymentReceived",
+            LightningNodeEvent::PaymentClaimable { .. } => "PaymentClaimable",
+            LightningNodeEvent::PaymentForwarded { .. } => "PaymentForwarded",
+            LightningNodeEvent::ChannelPending { .. } => "ChannelPending",
+            LightningNodeEvent::ChannelReady { .. } => "ChannelReady",
+            LightningNodeEvent::ChannelClosed { .. } => "ChannelClosed",
+        }
+    }
+}
+
+/// Schema version of the envelopes published by this version.
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// Schema version given to bare events, which were published before envelopes were used.
+pub const LEGACY_SCHEMA_VERSION: u32 = 0;
+
+/// A node event as it's sent over the bus.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventEnvelope {
+    /// Unique id of the event, the same for every delivery of it. Empty for legacy events.
+    pub id: String,
+    pub schema_version: u32,
+    /// Service which emitted the event, e.g. `lightning-node/<node id>`
+    pub source: String,
+    /// Unix timestamp in milliseconds of when the event was emitted
+    pub timestamp: u64,
+    pub event: LightningNodeEvent,
+}
+
+impl EventEnvelope {
+    pub fn new(id: String, source: String, timestamp: u64, event: LightningNodeEvent) -> Self {
+        Self {
+            id,
+            schema_version: EVENT_SCHEMA_VERSION,
+            source,
+            timestamp,
+            event,
+        }
+    }
+
+    /// Decodes an envelope, or a bare event of a publisher not using envelopes yet.
+    pub fn decode(content: &[u8]) -> Result<Self, serde_json::Error> {
+        match serde_json::from_slice(content) {
+            Ok(envelope) => Ok(envelope),
+            Err(err) => match serde_json::from_slice(content) {
+                Ok(event) => Ok(Self {
+                    id: String::new(),
+                    schema_version: LEGACY_SCHEMA_VERSION,
+                    source: String::new(),
+                    timestamp: 0,
+                    event,
+                }),
+                // The envelope error is the relevant one for current publishers
+                Err(_) => Err(err),
+            },
+        }
+    }
+
+    /// Legacy events carry no id, so they can't be deduplicated.
+    pub fn is_legacy(&self) -> bool {
+        self.schema_version == LEGACY_SCHEMA_VERSION
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventEnvelope, LightningNodeEvent, EVENT_SCHEMA_VERSION, LEGACY_SCHEMA_VERSION};
+
+    fn event() -> LightningNodeEvent {
+        LightningNodeEvent::PaymentReceived {
+            payment_hash: "hash".to_string(),
+            amount_msat: 1000,
+            offer_id: None,
+            custom_records: vec![],
+        }
+    }
+
+    #[test]
+    fn test_decode_envelope() {
+        let envelope = EventEnvelope::new("id".to_string(), "test".to_string(), 1, event());
+        let content = serde_json::to_vec(&envelope).unwrap();
+
+        let decoded = EventEnvelope::decode(&content).unwrap();
+        assert_eq!(decoded, envelope);
+        assert_eq!(decoded.schema_version, EVENT_SCHEMA_VERSION);
+        assert!(!decoded.is_legacy());
+    }
+
+    #[test]
+    fn test_decode_legacy_event() {
+        // Published before custom records were added
+        let content =
+            br#"{"PaymentReceived":{"payment_hash":"hash","amount_msat":1000,"offer_id":null}}"#;
+
+        let decoded = EventEnvelope::decode(content).unwrap();
+        assert_eq!(decoded.event, event());
+        assert_eq!(decoded.schema_version, LEGACY_SCHEMA_VERSION);
+        assert!(decoded.id.is_empty());
+        assert!(decoded.is_legacy());
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert!(EventEnvelope::decode(br#"{"Unknown":{}}"#).is_err());
+    }
+}
@@ -1,9 +1,92 @@
 use amqprs::{
-    callbacks::{DefaultChannelCallback, DefaultConnectionCallback},
-    channel::{Channel, ExchangeDeclareArguments},
+    callbacks::{ChannelCallback, DefaultChannelCallback, DefaultConnectionCallback},
+    channel::{BasicPublishArguments, Channel, ConfirmSelectArguments, ExchangeDeclareArguments},
     connection::{Connection, OpenConnectionArguments},
+    Ack, BasicProperties, Cancel, CloseChannel, Nack, Return,
 };
-use anyhow::Result;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use log::warn;
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    time::timeout,
+};
+
+use crate::config::RabbitMqConfig;
+
+const PUBLISH_CONFIRM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Broker answer to a published message, `multiple` covers all tags up to `delivery_tag`.
+#[derive(Debug, Clone, Copy)]
+pub struct PublishConfirm {
+    pub delivery_tag: u64,
+    pub multiple: bool,
+    pub acked: bool,
+}
+
+/// Channel callback forwarding publisher confirms to the publisher.
+pub struct PublisherConfirmsCallback {
+    sender: UnboundedSender<PublishConfirm>,
+}
+
+#[async_trait]
+impl ChannelCallback for PublisherConfirmsCallback {
+    async fn close(
+        &mut self,
+        channel: &Channel,
+        close: CloseChannel,
+    ) -> Result<(), amqprs::error::Error> {
+        DefaultChannelCallback.close(channel, close).await
+    }
+
+    async fn cancel(
+        &mut self,
+        channel: &Channel,
+        cancel: Cancel,
+    ) -> Result<(), amqprs::error::Error> {
+        DefaultChannelCallback.cancel(channel, cancel).await
+    }
+
+    async fn flow(
+        &mut self,
+        channel: &Channel,
+        active: bool,
+    ) -> Result<bool, amqprs::error::Error> {
+        DefaultChannelCallback.flow(channel, active).await
+    }
+
+    async fn publish_ack(&mut self, _channel: &Channel, ack: Ack) {
+        self.sender
+            .send(PublishConfirm {
+                delivery_tag: ack.delivery_tag(),
+                multiple: ack.mutiple(),
+                acked: true,
+            })
+            .ok();
+    }
+
+    async fn publish_nack(&mut self, _channel: &Channel, nack: Nack) {
+        self.sender
+            .send(PublishConfirm {
+                delivery_tag: nack.delivery_tag(),
+                multiple: nack.multiple(),
+                acked: false,
+            })
+            .ok();
+    }
+
+    async fn publish_return(
+        &mut self,
+        _channel: &Channel,
+        ret: Return,
+        _basic_properties: BasicProperties,
+        _content: Vec<u8>,
+    ) {
+        warn!("Message returned by broker: {:?}", ret);
+    }
+}
 
 pub async fn get_rabbitmq_connection(
     host: &str,
@@ -15,24 +98,26 @@ pub async fn get_rabbitmq_connection(
     let connection = Connection::open(&OpenConnectionArguments::new(
         host, port, username, password,
     ))
-    .await
-    .unwrap();
+    .await?;
 
     connection
         .register_callback(DefaultConnectionCallback)
-        .await
-        .unwrap();
-
-    // open a channel on the connection
-    let channel = connection.open_channel(None).await.unwrap();
-    channel
-        .register_callback(DefaultChannelCallback)
-        .await
-        .unwrap();
+        .await?;
 
     Ok(connection)
 }
 
+/// Opens a connection to the configured broker.
+pub async fn connect(config: &RabbitMqConfig) -> Result<Connection> {
+    get_rabbitmq_connection(
+        &config.rabbitmq_host,
+        config.rabbitmq_port,
+        &config.rabbitmq_username,
+        &config.rabbitmq_password,
+    )
+    .await
+}
+
 pub async fn ensure_exchange_created(
     channel: &Channel,
     exhange_name: &str,
@@ -46,3 +131,59 @@ pub async fn ensure_exchange_created(
 
     Ok(())
 }
+
+/// Publishes on a channel in confirm mode, every publish waits until the broker confirmed it.
+pub struct ConfirmedPublisher {
+    confirms: UnboundedReceiver<PublishConfirm>,
+    next_delivery_tag: u64,
+}
+
+impl ConfirmedPublisher {
+    /// Puts the channel into confirm mode, the channel's callback is replaced.
+    pub async fn enable(channel: &Channel) -> Result<Self> {
+        let (sender, confirms) = mpsc::unbounded_channel();
+
+        channel
+            .register_callback(PublisherConfirmsCallback { sender })
+            .await?;
+        channel
+            .confirm_select(ConfirmSelectArguments::default())
+            .await?;
+
+        Ok(Self {
+            confirms,
+            next_delivery_tag: 1,
+        })
+    }
+
+    /// Publishes the message on the channel `enable` was called with and waits for its confirm.
+    pub async fn publish(
+        &mut self,
+        channel: &Channel,
+        properties: BasicProperties,
+        content: Vec<u8>,
+        args: BasicPublishArguments,
+    ) -> Result<()> {
+        // Delivery tags are assigned in publish order
+        let delivery_tag = self.next_delivery_tag;
+        channel.basic_publish(properties, content, args).await?;
+        self.next_delivery_tag += 1;
+
+        loop {
+            let confirm = match timeout(PUBLISH_CONFIRM_TIMEOUT, self.confirms.recv()).await {
+                Ok(Some(confirm)) => confirm,
+                Ok(None) => bail!("Channel closed before message was confirmed"),
+                Err(_) => bail!("Message was not confirmed in time"),
+            };
+
+            let covers_tag = confirm.delivery_tag == delivery_tag
+                || (confirm.multiple && confirm.delivery_tag > delivery_tag);
+            if covers_tag {
+                if !confirm.acked {
+                    bail!("Message was rejected by broker");
+                }
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use std::time::Duration;
+
+use amqprs::{
+    channel::{
+        BasicAckArguments, BasicGetArguments, BasicNackArguments, BasicPublishArguments, Channel,
+        QueueBindArguments, QueueDeclareArguments,
+    },
+    BasicProperties, FieldName, FieldTable, FieldValue,
+};
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{config::RabbitMqConfig, rabbitmq, rabbitmq::ConfirmedPublisher};
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+
+const ATTEMPT_HEADER: &str = "x-walletka-attempt";
+const ERROR_HEADER: &str = "x-walletka-error";
+
+/// How often and how late a failed event is delivered again before it's dead-lettered.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::from_settings(None, None)
+    }
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &RabbitMqConfig) -> Self {
+        Self::from_settings(
+            config.event_retry_max_attempts,
+            config.event_retry_initial_delay_ms,
+        )
+    }
+
+    /// Falls back to the defaults for unset values.
+    pub fn from_settings(max_attempts: Option<u32>, initial_delay_ms: Option<u64>) -> Self {
+        Self {
+            max_attempts: max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS).max(1),
+            initial_delay: initial_delay_ms
+                .map(Duration::from_millis)
+                .unwrap_or(DEFAULT_INITIAL_DELAY),
+        }
+    }
+
+    /// Delay before the given retry, doubling with every attempt.
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.initial_delay * 2u32.saturating_pow(retry.saturating_sub(1))
+    }
+}
+
+/// An event which failed every attempt or couldn't be parsed.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetter {
+    pub content: String,
+    pub attempts: u32,
+    pub error: Option<String>,
+}
+
+pub fn dead_letter_queue(queue: &str) -> String {
+    format!("{}.dead-letter", queue)
+}
+
+/// The delay is part of the name, as the TTL of an existing queue can't be changed.
+fn delay_queue(queue: &str, delay: Duration) -> String {
+    format!("{}.retry.{}ms", queue, delay.as_millis())
+}
+
+/// Delay queues and dead-letter queue of a consumer queue.
+pub struct RetryQueues {
+    queue: String,
+    dead_letter_exchange: String,
+    policy: RetryPolicy,
+}
+
+impl RetryQueues {
+    pub fn new(config: &RabbitMqConfig, queue: &str) -> Self {
+        Self {
+            queue: queue.to_string(),
+            dead_letter_exchange: format!("{}.dead-letter", config.lightning_node_exchange),
+            policy: RetryPolicy::from_config(config),
+        }
+    }
+
+    /// Declares a delay queue per retry and the dead-letter queue.
+    ///
+    /// Messages expire from a delay queue after its delay and go back to the consumer queue
+    /// through the default exchange. Delay queues of a previous retry policy are left alone,
+    /// they still return their messages.
+    pub async fn declare(&self, channel: &Channel) -> Result<()> {
+        for retry in 1..self.policy.max_attempts {
+            let delay = self.policy.delay(retry);
+            let mut arguments = FieldTable::new();
+            arguments.insert(
+                field_name("x-message-ttl"),
+                FieldValue::l(delay.as_millis() as i64),
+            );
+            arguments.insert(
+                field_name("x-dead-letter-exchange"),
+                FieldValue::S("".to_string().try_into()?),
+            );
+            arguments.insert(
+                field_name("x-dead-letter-routing-key"),
+                FieldValue::S(self.queue.clone().try_into()?),
+            );
+
+            channel
+                .queue_declare(
+                    QueueDeclareArguments::new(&delay_queue(&self.queue, delay))
+                        .durable(true)
+                        .arguments(arguments)
+                        .finish(),
+                )
+                .await?;
+        }
+
+        rabbitmq::ensure_exchange_created(channel, &self.dead_letter_exchange, "direct").await?;
+        channel
+            .queue_declare(
+                QueueDeclareArguments::new(&dead_letter_queue(&self.queue))
+                    .durable(true)
+                    .finish(),
+            )
+            .await?;
+        channel
+            .queue_bind(QueueBindArguments::new(
+                &dead_letter_queue(&self.queue),
+                &self.dead_letter_exchange,
+                &self.queue,
+            ))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Delivers the message again after the delay of the attempt, or dead-letters it once all
+    /// attempts failed.
+    pub async fn retry_or_dead_letter(
+        &self,
+        channel: &Channel,
+        publisher: &mut ConfirmedPublisher,
+        delivery_tag: u64,
+        properties: &BasicProperties,
+        content: Vec<u8>,
+        error: &str,
+    ) -> Result<()> {
+        let attempts = previous_attempts(properties) + 1;
+        if attempts < self.policy.max_attempts {
+            let delay_queue = delay_queue(&self.queue, self.policy.delay(attempts));
+            let headers = headers(attempts, None)?;
+            self.hand_over(
+                channel,
+                publisher,
+                delivery_tag,
+                "",
+                &delay_queue,
+                headers,
+                content,
+            )
+            .await
+        } else {
+            self.dead_letter(channel, publisher, delivery_tag, properties, content, error)
+                .await
+        }
+    }
+
+    /// Dead-letters the message without retrying, for messages which can never be handled.
+    pub async fn dead_letter(
+        &self,
+        channel: &Channel,
+        publisher: &mut ConfirmedPublisher,
+        delivery_tag: u64,
+        properties: &BasicProperties,
+        content: Vec<u8>,
+        error: &str,
+    ) -> Result<()> {
+        let headers = headers(previous_attempts(properties) + 1, Some(error))?;
+        self.hand_over(
+            channel,
+            publisher,
+            delivery_tag,
+            &self.dead_letter_exchange,
+            &self.queue,
+            headers,
+            content,
+        )
+        .await
+    }
+
+    /// Publishes the message and acks the original delivery once the broker confirmed the
+    /// copy. The original is requeued if publishing fails, so the message is never lost.
+    #[allow(clippy::too_many_arguments)]
+    async fn hand_over(
+        &self,
+        channel: &Channel,
+        publisher: &mut ConfirmedPublisher,
+        delivery_tag: u64,
+        exchange: &str,
+        routing_key: &str,
+        headers: FieldTable,
+        content: Vec<u8>,
+    ) -> Result<()> {
+        let published = publisher
+            .publish(
+                channel,
+                BasicProperties::default()
+                    .with_delivery_mode(2)
+                    .with_headers(headers)
+                    .finish(),
+                content,
+                BasicPublishArguments::new(exchange, routing_key),
+            )
+            .await;
+
+        match published {
+            Ok(_) => {
+                channel
+                    .basic_ack(BasicAckArguments::new(delivery_tag, false))
+                    .await?;
+                Ok(())
+            }
+            Err(err) => {
+                channel
+                    .basic_nack(BasicNackArguments::new(delivery_tag, false, true))
+                    .await?;
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Number of times the message was delivered to the consumer before.
+pub fn previous_attempts(properties: &BasicProperties) -> u32 {
+    match properties
+        .headers()
+        .and_then(|headers| headers.get(&field_name(ATTEMPT_HEADER)))
+    {
+        Some(FieldValue::I(attempts)) => *attempts as u32,
+        _ => 0,
+    }
+}
+
+/// Returns up to `limit` dead letters of the consumer queue without removing them.
+pub async fn list_dead_letters(
+    channel: &Channel,
+    queue: &str,
+    limit: usize,
+) -> Result<Vec<DeadLetter>> {
+    let mut dead_letters = vec![];
+    let mut last_delivery_tag = None;
+
+    while dead_letters.len() < limit {
+        let (get_ok, properties, content) = match channel
+            .basic_get(BasicGetArguments::new(&dead_letter_queue(queue)))
+            .await?
+        {
+            Some(message) => message,
+            None => break,
+        };
+        last_delivery_tag = Some(get_ok.delivery_tag());
+
+        dead_letters.push(DeadLetter {
+            content: String::from_utf8_lossy(&content).to_string(),
+            attempts: previous_attempts(&properties),
+            error: match properties
+                .headers()
+                .and_then(|headers| headers.get(&field_name(ERROR_HEADER)))
+            {
+                Some(FieldValue::S(error)) => Some(error.to_string()),
+                _ => None,
+            },
+        });
+    }
+
+    // Hand all fetched messages back to the queue
+    if let Some(delivery_tag) = last_delivery_tag {
+        channel
+            .basic_nack(BasicNackArguments::new(delivery_tag, true, true))
+            .await?;
+    }
+
+    Ok(dead_letters)
+}
+
+/// Moves all dead letters back to the consumer queue with a fresh attempt count.
+///
+/// Returns the number of requeued messages.
+pub async fn requeue_dead_letters(channel: &Channel, queue: &str) -> Result<u64> {
+    let mut publisher = ConfirmedPublisher::enable(channel).await?;
+    let mut requeued = 0;
+
+    while let Some((get_ok, _, content)) = channel
+        .basic_get(BasicGetArguments::new(&dead_letter_queue(queue)))
+        .await?
+    {
+        // A dead letter which isn't confirmed stays in the dead-letter queue
+        publisher
+            .publish(
+                channel,
+                BasicProperties::default().with_delivery_mode(2).finish(),
+                content,
+                BasicPublishArguments::new("", queue),
+            )
+            .await?;
+        channel
+            .basic_ack(BasicAckArguments::new(get_ok.delivery_tag(), false))
+            .await?;
+        requeued += 1;
+    }
+
+    Ok(requeued)
+}
+
+fn headers(attempts: u32, error: Option<&str>) -> Result<FieldTable> {
+    let mut headers = FieldTable::new();
+    headers.insert(field_name(ATTEMPT_HEADER), FieldValue::I(attempts as i32));
+    if let Some(error) = error {
+        headers.insert(
+            field_name(ERROR_HEADER),
+            FieldValue::S(error.to_string().try_into()?),
+        );
+    }
+
+    Ok(headers)
+}
+
+fn field_name(name: &str) -> FieldName {
+    name.try_into().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::RetryPolicy;
+
+    #[test]
+    fn test_delay_doubles() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            initial_delay: Duration::from_millis(100),
+        };
+
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(4), Duration::from_millis(800));
+    }
+
+    #[test]
+    fn test_from_settings() {
+        let policy = RetryPolicy::from_settings(None, None);
+        assert_eq!(policy.max_attempts, 5);
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+
+        // At least one attempt is made
+        let policy = RetryPolicy::from_settings(Some(0), Some(10));
+        assert_eq!(policy.max_attempts, 1);
+        assert_eq!(policy.delay(3), Duration::from_millis(40));
+    }
+}
@@ -0,0 +1,316 @@
+use std::{collections::HashMap, fmt, str::FromStr, sync::Arc};
+
+use anyhow::{bail, Result};
+use log::warn;
+use tonic::{
+    metadata::{Ascii, MetadataValue},
+    service::Interceptor,
+    transport::ClientTlsConfig,
+    Request, Status,
+};
+
+pub mod tls;
+
+const AUTHORIZATION_HEADER: &str = "authorization";
+const BEARER_PREFIX: &str = "Bearer ";
+
+/// Permission granted to a token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Queries which don't change any state.
+    ReadOnly,
+    /// Creating and managing invoices, nothing else.
+    Invoice,
+    /// Sending payments, includes the read-only and invoice scopes.
+    Payments,
+    /// Everything, including channel, peer and wallet management.
+    Admin,
+}
+
+impl Scope {
+    /// Whether a token with this scope may call a method which requires `required`.
+    pub fn allows(self, required: Scope) -> bool {
+        match self {
+            Scope::Admin => true,
+            Scope::Payments => required != Scope::Admin,
+            Scope::Invoice => required == Scope::Invoice,
+            Scope::ReadOnly => required == Scope::ReadOnly,
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "readonly" | "read-only" => Ok(Scope::ReadOnly),
+            "invoice" | "invoice-only" => Ok(Scope::Invoice),
+            "payments" => Ok(Scope::Payments),
+            "admin" => Ok(Scope::Admin),
+            _ => bail!("Unknown scope \"{}\"", s),
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::ReadOnly => write!(f, "readonly"),
+            Scope::Invoice => write!(f, "invoice"),
+            Scope::Payments => write!(f, "payments"),
+            Scope::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// Server side interceptor, checks the bearer token and stores its scope in the request.
+///
+/// Clients which presented a certificate trusted by the server's client CA get
+/// `client_cert_scope` without a token. Only when authentication is explicitly disabled every
+/// caller gets the admin scope.
+#[derive(Clone)]
+pub struct ServerAuthInterceptor {
+    tokens: Arc<HashMap<String, Scope>>,
+    client_cert_scope: Option<Scope>,
+    disabled: bool,
+}
+
+impl ServerAuthInterceptor {
+    /// Parses tokens in the `scope:token,scope:token` format.
+    ///
+    /// Fails when neither tokens nor client certificates are configured, unless `disabled`.
+    pub fn from_config(
+        tokens: Option<&str>,
+        client_cert_scope: Option<Scope>,
+        disabled: bool,
+    ) -> Result<Self> {
+        if disabled {
+            warn!("gRPC authentication is disabled, every caller has the admin scope!");
+            return Ok(Self {
+                tokens: Arc::new(HashMap::new()),
+                client_cert_scope: None,
+                disabled,
+            });
+        }
+
+        let mut parsed = HashMap::new();
+
+        for entry in tokens.unwrap_or_default().split(',') {
+            let entry = entry.trim();
+            if entry.is_empty() {
+                continue;
+            }
+            let (scope, token) = match entry.split_once(':') {
+                Some((scope, token)) if !token.is_empty() => (Scope::from_str(scope)?, token),
+                _ => bail!("Invalid auth token entry, expected scope:token"),
+            };
+            if parsed.insert(token.to_string(), scope).is_some() {
+                bail!("Auth token is configured more than once");
+            }
+        }
+
+        if parsed.is_empty() && client_cert_scope.is_none() {
+            bail!("No gRPC auth tokens or client certificates configured, set GRPC_AUTH_TOKENS or GRPC_AUTH_DISABLED=true");
+        }
+
+        Ok(Self {
+            tokens: Arc::new(parsed),
+            client_cert_scope,
+            disabled,
+        })
+    }
+}
+
+impl Interceptor for ServerAuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if self.disabled {
+            request.extensions_mut().insert(Scope::Admin);
+            return Ok(request);
+        }
+
+        // Certificates only get here after the TLS layer verified them against the client CA
+        if let Some(scope) = self.client_cert_scope {
+            let has_client_cert = request
+                .peer_certs()
+                .map(|certs| !certs.is_empty())
+                .unwrap_or(false);
+            if has_client_cert {
+                request.extensions_mut().insert(scope);
+                return Ok(request);
+            }
+        }
+
+        let token = match request.metadata().get(AUTHORIZATION_HEADER) {
+            Some(value) => match value.to_str() {
+                Ok(value) => value.strip_prefix(BEARER_PREFIX),
+                Err(_) => None,
+            },
+            None => None,
+        };
+
+        match token.and_then(|token| self.tokens.get(token)) {
+            Some(scope) => {
+                let scope = *scope;
+                request.extensions_mut().insert(scope);
+                Ok(request)
+            }
+            None => Err(Status::new(
+                tonic::Code::Unauthenticated,
+                "Missing or invalid auth token",
+            )),
+        }
+    }
+}
+
+/// Fails unless the interceptor granted a scope which allows `required`.
+// Handlers return tonic's `Status` as is
+#[allow(clippy::result_large_err)]
+pub fn authorize<T>(request: &Request<T>, required: Scope) -> Result<(), Status> {
+    match request.extensions().get::<Scope>() {
+        Some(scope) if scope.allows(required) => Ok(()),
+        Some(scope) => Err(Status::new(
+            tonic::Code::PermissionDenied,
+            format!("Token scope {} doesn't allow {} calls", scope, required),
+        )),
+        None => Err(Status::new(
+            tonic::Code::Unauthenticated,
+            "Request was not authenticated",
+        )),
+    }
+}
+
+/// How a client authenticates to a gRPC service.
+#[derive(Debug, Clone, Default)]
+pub struct GrpcCredentials {
+    pub auth_token: Option<String>,
+    /// PEM certificate of the server, the connection uses TLS and trusts only this certificate
+    pub tls_cert_path: Option<String>,
+    /// PEM certificate and key presented to the server for mutual TLS
+    pub tls_client_cert_path: Option<String>,
+    pub tls_client_key_path: Option<String>,
+}
+
+impl GrpcCredentials {
+    pub fn interceptor(&self) -> Result<ClientAuthInterceptor> {
+        ClientAuthInterceptor::new(self.auth_token.clone())
+    }
+
+    /// Returns `None` for plaintext connections.
+    pub fn tls_config(&self) -> Result<Option<ClientTlsConfig>> {
+        let client_identity = match (&self.tls_client_cert_path, &self.tls_client_key_path) {
+            (Some(cert_path), Some(key_path)) => Some((cert_path.as_str(), key_path.as_str())),
+            (None, None) => None,
+            _ => bail!("Both client certificate and key are required for mutual TLS"),
+        };
+
+        match &self.tls_cert_path {
+            Some(cert_path) => Ok(Some(tls::client_tls_config(cert_path, client_identity)?)),
+            None if client_identity.is_some() => {
+                bail!("Mutual TLS requires the server certificate to be pinned")
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Client side interceptor, attaches the bearer token to every request.
+#[derive(Clone)]
+pub struct ClientAuthInterceptor {
+    authorization: Option<MetadataValue<Ascii>>,
+}
+
+impl ClientAuthInterceptor {
+    pub fn new(token: Option<String>) -> Result<Self> {
+        let authorization = match token {
+            Some(token) if !token.is_empty() => Some(MetadataValue::try_from(format!(
+                "{}{}",
+                BEARER_PREFIX, token
+            ))?),
+            _ => None,
+        };
+
+        Ok(Self { authorization })
+    }
+}
+
+impl Interceptor for ClientAuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(authorization) = &self.authorization {
+            request
+                .metadata_mut()
+                .insert(AUTHORIZATION_HEADER, authorization.clone());
+        }
+
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::{metadata::MetadataValue, service::Interceptor, Code, Request};
+
+    use super::{authorize, Scope, ServerAuthInterceptor};
+
+    fn request_with_token(token: &str) -> Request<()> {
+        let mut request = Request::new(());
+        request.metadata_mut().insert(
+            "authorization",
+            MetadataValue::try_from(format!("Bearer {}", token)).unwrap(),
+        );
+        request
+    }
+
+    #[test]
+    fn test_scope_allows() {
+        assert!(Scope::Admin.allows(Scope::Admin));
+        assert!(Scope::Payments.allows(Scope::Invoice));
+        assert!(Scope::Payments.allows(Scope::ReadOnly));
+        assert!(!Scope::Payments.allows(Scope::Admin));
+        assert!(!Scope::Invoice.allows(Scope::ReadOnly));
+        assert!(!Scope::ReadOnly.allows(Scope::Invoice));
+    }
+
+    #[test]
+    fn test_parse_tokens() {
+        let mut auth =
+            ServerAuthInterceptor::from_config(Some("admin:a, readonly:b,"), None, false).unwrap();
+
+        let request = auth.call(request_with_token("b")).unwrap();
+        assert!(authorize(&request, Scope::ReadOnly).is_ok());
+        assert_eq!(
+            authorize(&request, Scope::Payments).unwrap_err().code(),
+            Code::PermissionDenied
+        );
+
+        let request = auth.call(request_with_token("a")).unwrap();
+        assert!(authorize(&request, Scope::Admin).is_ok());
+
+        let err = auth.call(request_with_token("c")).unwrap_err();
+        assert_eq!(err.code(), Code::Unauthenticated);
+        assert_eq!(
+            auth.call(Request::new(())).unwrap_err().code(),
+            Code::Unauthenticated
+        );
+    }
+
+    #[test]
+    fn test_invalid_tokens() {
+        assert!(ServerAuthInterceptor::from_config(Some("admin"), None, false).is_err());
+        assert!(ServerAuthInterceptor::from_config(Some("root:a"), None, false).is_err());
+        assert!(
+            ServerAuthInterceptor::from_config(Some("admin:a,readonly:a"), None, false).is_err()
+        );
+    }
+
+    #[test]
+    fn test_requires_credentials_unless_disabled() {
+        assert!(ServerAuthInterceptor::from_config(None, None, false).is_err());
+        assert!(ServerAuthInterceptor::from_config(Some(""), None, false).is_err());
+        assert!(ServerAuthInterceptor::from_config(None, Some(Scope::ReadOnly), false).is_ok());
+
+        let mut auth = ServerAuthInterceptor::from_config(None, None, true).unwrap();
+        let request = auth.call(Request::new(())).unwrap();
+        assert!(authorize(&request, Scope::Admin).is_ok());
+    }
+}
@@ -0,0 +1,116 @@
+use std::{fs, path::Path, str::FromStr};
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use tonic::transport::{Certificate, ClientTlsConfig, Identity, ServerTlsConfig};
+
+use crate::Scope;
+
+const CERT_FILE_NAME: &str = "tls.cert";
+const KEY_FILE_NAME: &str = "tls.key";
+
+/// Builds the server TLS config from the certificate in `dir`, generating a self-signed one on
+/// the first run.
+///
+/// With a client CA, clients may authenticate with a certificate signed by it instead of a token.
+pub fn server_tls_config(
+    dir: &Path,
+    hostnames: &[String],
+    client_ca_path: Option<&str>,
+) -> Result<ServerTlsConfig> {
+    let (cert, key) = load_or_generate_identity(dir, hostnames)?;
+    let mut tls_config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+
+    if let Some(client_ca_path) = client_ca_path.filter(|path| !path.is_empty()) {
+        let client_ca = fs::read(client_ca_path)
+            .with_context(|| format!("Cannot read client CA {}", client_ca_path))?;
+        tls_config = tls_config
+            .client_ca_root(Certificate::from_pem(client_ca))
+            // Token authenticated clients don't have a certificate
+            .client_auth_optional(true);
+    }
+
+    Ok(tls_config)
+}
+
+/// Parses comma separated hostnames for the generated certificate.
+pub fn parse_hostnames(hostnames: Option<&str>) -> Vec<String> {
+    hostnames
+        .unwrap_or_default()
+        .split(',')
+        .map(|hostname| hostname.trim().to_string())
+        .filter(|hostname| !hostname.is_empty())
+        .collect()
+}
+
+/// Scope of clients authenticated by a certificate, `None` without mutual TLS.
+///
+/// The scope has to be set explicitly when a client CA is configured.
+pub fn client_cert_scope(
+    tls_enabled: bool,
+    client_ca_path: Option<&str>,
+    scope: Option<&str>,
+) -> Result<Option<Scope>> {
+    match client_ca_path {
+        Some(path) if !path.is_empty() => {}
+        _ => return Ok(None),
+    }
+    if !tls_enabled {
+        bail!("A client CA requires TLS to be enabled");
+    }
+
+    match scope {
+        Some(scope) if !scope.is_empty() => Ok(Some(Scope::from_str(scope)?)),
+        _ => bail!("A client CA requires the scope of client certificates to be set"),
+    }
+}
+
+/// Trusts only the given server certificate, which pins self-signed certificates.
+pub fn client_tls_config(
+    cert_path: &str,
+    client_identity: Option<(&str, &str)>,
+) -> Result<ClientTlsConfig> {
+    let cert = fs::read(cert_path)
+        .with_context(|| format!("Cannot read server certificate {}", cert_path))?;
+    let mut tls_config = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(cert));
+
+    if let Some((client_cert_path, client_key_path)) = client_identity {
+        let client_cert = fs::read(client_cert_path)
+            .with_context(|| format!("Cannot read client certificate {}", client_cert_path))?;
+        let client_key = fs::read(client_key_path)
+            .with_context(|| format!("Cannot read client key {}", client_key_path))?;
+        tls_config = tls_config.identity(Identity::from_pem(client_cert, client_key));
+    }
+
+    Ok(tls_config)
+}
+
+fn load_or_generate_identity(dir: &Path, hostnames: &[String]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let cert_path = dir.join(CERT_FILE_NAME);
+    let key_path = dir.join(KEY_FILE_NAME);
+
+    if cert_path.exists() && key_path.exists() {
+        return Ok((fs::read(cert_path)?, fs::read(key_path)?));
+    }
+
+    let mut subject_alt_names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+    subject_alt_names.extend(hostnames.iter().cloned());
+    let cert = rcgen::generate_simple_self_signed(subject_alt_names)?;
+    let cert_pem = cert.serialize_pem()?;
+    let key_pem = cert.serialize_private_key_pem();
+
+    fs::create_dir_all(dir)?;
+    fs::write(&key_path, &key_pem)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&key_path, fs::Permissions::from_mode(0o600))?;
+    }
+    fs::write(&cert_path, &cert_pem)?;
+    info!(
+        "Generated self-signed TLS certificate {}, give it to clients to pin",
+        cert_path.display()
+    );
+
+    Ok((cert_pem.into_bytes(), key_pem.into_bytes()))
+}
@@ -1,4 +1,7 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("../../protos/node.proto")?;
+    // ChannelPolicyMessage uses proto3 optional fields
+    tonic_build::configure()
+        .protoc_arg("--experimental_allow_proto3_optional")
+        .compile(&["../../protos/node.proto"], &["../../protos"])?;
     Ok(())
 }
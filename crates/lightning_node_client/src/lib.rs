@@ -1,28 +1,42 @@
 use anyhow::Result;
-use proto::node_client::NodeClient;
+use grpc_auth::ClientAuthInterceptor;
+pub use grpc_auth::GrpcCredentials;
+use proto::{node_client::NodeClient, ErrorReason};
 use std::time::Duration;
-use tonic::transport::Channel;
+use tonic::{codegen::InterceptedService, transport::Channel, Status};
+use tonic_types::StatusExt;
 use tower::ServiceBuilder;
 
 pub mod proto {
     tonic::include_proto!("node_api_service");
 }
 
+pub type LightningNodeClient = NodeClient<InterceptedService<Channel, ClientAuthInterceptor>>;
+
 pub async fn get_lightning_node_client(
     address: String,
     keep_alive: bool,
-) -> Result<NodeClient<Channel>> {
-    let channel = Channel::from_shared(address)?
+    credentials: &GrpcCredentials,
+) -> Result<LightningNodeClient> {
+    let mut endpoint = Channel::from_shared(address)?
         .connect_timeout(Duration::from_secs(10)) // Set connection timeout
-        .keep_alive_while_idle(keep_alive) // Set keep-alive
-        .connect()
-        .await?;
+        .keep_alive_while_idle(keep_alive); // Set keep-alive
+    if let Some(tls_config) = credentials.tls_config()? {
+        endpoint = endpoint.tls_config(tls_config)?;
+    }
+    let channel = endpoint.connect().await?;
 
     let channel = ServiceBuilder::new()
-        // Interceptors can be also be applied as middleware
-        //.layer(tonic::service::interceptor(intercept))
-        //.layer_fn(AuthSvc::new)
+        .layer(tonic::service::interceptor(credentials.interceptor()?))
         .service(channel);
 
     Ok(NodeClient::new(channel))
 }
+
+/// Reason of a failed call, taken from the `ErrorInfo` detail the node attaches to errors.
+pub fn error_reason(status: &Status) -> ErrorReason {
+    status
+        .get_details_error_info()
+        .and_then(|info| ErrorReason::from_str_name(&info.reason))
+        .unwrap_or(ErrorReason::Unspecified)
+}
@@ -1,10 +1,177 @@
+use std::fmt;
+
+use anyhow::{bail, Result};
+use grpc_auth::{tls, Scope};
+use ldk_node::bitcoin::Network;
 use serde::Deserialize;
 
+use crate::network;
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct LightningNodeConfig {
     pub lightning_data_dir: String,
     pub lightning_node_port: u16,
     pub lightning_node_grpc_port: u16,
+    /// Announced node alias, at most 32 bytes
+    pub lightning_node_alias: Option<String>,
     pub mnemonic: Option<String>,
-    pub esplora_server_url: String,
+    pub bitcoin_network: Option<String>,
+    /// "esplora" or "bitcoind", defaults to esplora
+    pub chain_source: Option<String>,
+    pub esplora_server_url: Option<String>,
+    pub bitcoind_rpc_host: Option<String>,
+    pub bitcoind_rpc_port: Option<u16>,
+    pub bitcoind_rpc_user: Option<String>,
+    pub bitcoind_rpc_password: Option<String>,
+    /// "p2p" or "rgs", defaults to rgs when a server is configured or known for the network
+    pub gossip_source: Option<String>,
+    pub rgs_server_url: Option<String>,
+    /// Comma separated `scope:token` pairs
+    pub grpc_auth_tokens: Option<String>,
+    /// Serves gRPC without authentication, required when no tokens or client CA are set
+    pub grpc_auth_disabled: Option<bool>,
+    /// Serves gRPC over TLS with a certificate generated in the data dir on the first run
+    pub lightning_node_grpc_tls: Option<bool>,
+    /// Comma separated hostnames added to the generated certificate besides localhost
+    pub lightning_node_grpc_tls_hostnames: Option<String>,
+    /// PEM CA which signs client certificates, enables mutual TLS
+    pub lightning_node_grpc_tls_client_ca: Option<String>,
+    /// Scope granted to clients with a certificate, required with a client CA
+    pub lightning_node_grpc_tls_client_scope: Option<String>,
+    /// LSPS2 opening fee terms of JIT channels
+    pub jit_channel_min_fee_msat: Option<u64>,
+    pub jit_channel_fee_proportional_millionths: Option<u32>,
+    pub jit_channel_min_payment_size_msat: Option<u64>,
+    pub jit_channel_max_payment_size_msat: Option<u64>,
+    /// How long an intercept SCID accepts payments after it was handed out
+    pub jit_channel_expiry_secs: Option<u64>,
+    /// Unused intercept SCIDs a client may hold at once, defaults to 3
+    pub jit_channel_max_pending_per_client: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ChainSource {
+    Esplora {
+        server_url: String,
+    },
+    BitcoindRpc {
+        host: String,
+        port: u16,
+        user: String,
+        password: String,
+    },
+}
+
+impl fmt::Display for ChainSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainSource::Esplora { .. } => write!(f, "esplora"),
+            ChainSource::BitcoindRpc { .. } => write!(f, "bitcoind"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum GossipSource {
+    P2p,
+    Rgs { server_url: String },
+}
+
+impl fmt::Display for GossipSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GossipSource::P2p => write!(f, "p2p"),
+            GossipSource::Rgs { .. } => write!(f, "rgs"),
+        }
+    }
+}
+
+impl LightningNodeConfig {
+    pub fn grpc_tls_hostnames(&self) -> Vec<String> {
+        tls::parse_hostnames(self.lightning_node_grpc_tls_hostnames.as_deref())
+    }
+
+    pub fn grpc_client_cert_scope(&self) -> Result<Option<Scope>> {
+        tls::client_cert_scope(
+            self.lightning_node_grpc_tls.unwrap_or(false),
+            self.lightning_node_grpc_tls_client_ca.as_deref(),
+            self.lightning_node_grpc_tls_client_scope.as_deref(),
+        )
+    }
+
+    /// Resolves the chain source, filling in network defaults and failing on incomplete settings.
+    pub fn chain_source(&self, network: Network) -> Result<ChainSource> {
+        match self
+            .chain_source
+            .as_deref()
+            .map(|s| s.to_lowercase())
+            .as_deref()
+        {
+            None | Some("esplora") => {
+                let server_url = match &self.esplora_server_url {
+                    Some(url) if !url.is_empty() => url.clone(),
+                    _ => match network::default_esplora_server_url(network) {
+                        Some(url) => url.to_string(),
+                        None => bail!("ESPLORA_SERVER_URL is required on {}", network),
+                    },
+                };
+
+                Ok(ChainSource::Esplora { server_url })
+            }
+            Some("bitcoind") => {
+                let (user, password) =
+                    match (&self.bitcoind_rpc_user, &self.bitcoind_rpc_password) {
+                        (Some(user), Some(password)) => (user.clone(), password.clone()),
+                        _ => bail!(
+                            "BITCOIND_RPC_USER and BITCOIND_RPC_PASSWORD are required for the bitcoind chain source"
+                        ),
+                    };
+
+                Ok(ChainSource::BitcoindRpc {
+                    host: self
+                        .bitcoind_rpc_host
+                        .clone()
+                        .unwrap_or("127.0.0.1".to_string()),
+                    port: self
+                        .bitcoind_rpc_port
+                        .unwrap_or(network::default_bitcoind_rpc_port(network)),
+                    user,
+                    password,
+                })
+            }
+            Some(source) => bail!(
+                "Unknown chain source \"{}\", expected esplora or bitcoind",
+                source
+            ),
+        }
+    }
+
+    /// Resolves the gossip source, RGS needs a server configured or known for the network.
+    pub fn gossip_source(&self, network: Network) -> Result<GossipSource> {
+        let rgs_server_url = match &self.rgs_server_url {
+            Some(url) if !url.is_empty() => Some(url.clone()),
+            _ => network::default_rgs_server_url(network).map(|url| url.to_string()),
+        };
+
+        match self
+            .gossip_source
+            .as_deref()
+            .map(|s| s.to_lowercase())
+            .as_deref()
+        {
+            None => Ok(match rgs_server_url {
+                Some(server_url) => GossipSource::Rgs { server_url },
+                None => GossipSource::P2p,
+            }),
+            Some("p2p") => Ok(GossipSource::P2p),
+            Some("rgs") => match rgs_server_url {
+                Some(server_url) => Ok(GossipSource::Rgs { server_url }),
+                None => bail!(
+                    "RGS_SERVER_URL is required for rapid gossip sync on {}",
+                    network
+                ),
+            },
+            Some(source) => bail!("Unknown gossip source \"{}\", expected p2p or rgs", source),
+        }
+    }
 }
@@ -1,16 +1,18 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, path::Path, sync::Arc};
 
 use anyhow::Result;
 use dotenv::dotenv;
-use events::config::RabbitMqConfig;
+use grpc_auth::{tls, ServerAuthInterceptor};
 use log::info;
 use tonic::transport::Server;
 
 use crate::server::{node_api::node_server::NodeServer, LightningNodeGrpcServer};
 
 mod config;
+mod network;
 mod processor;
 mod server;
+mod utils;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -19,25 +21,41 @@ async fn main() -> Result<()> {
 
     let config: config::LightningNodeConfig =
         envy::from_env::<config::LightningNodeConfig>().unwrap();
-    let rabbitmq_config = envy::from_env::<RabbitMqConfig>().unwrap();
+    let events = events::event_bus::from_env().await?;
+    let auth = ServerAuthInterceptor::from_config(
+        config.grpc_auth_tokens.as_deref(),
+        config.grpc_client_cert_scope()?,
+        config.grpc_auth_disabled.unwrap_or(false),
+    )?;
 
     info!("Starting Lightning node...");
 
-    let node_processor =
-        Arc::new(processor::NodeProcessor::new(config.clone(), rabbitmq_config).await?);
+    let node_processor = Arc::new(processor::NodeProcessor::new(config.clone(), events).await?);
     node_processor.start()?;
 
     let addr = SocketAddr::from(([0, 0, 0, 0], config.lightning_node_grpc_port));
-    let node_service = NodeServer::new(LightningNodeGrpcServer {
-        node: node_processor,
-    });
+    let node_service = NodeServer::with_interceptor(
+        LightningNodeGrpcServer {
+            node: node_processor,
+        },
+        auth,
+    );
 
     info!(
         "Starting grpc server at :{}",
         config.lightning_node_grpc_port
     );
 
-    Server::builder()
+    let mut server = Server::builder();
+    if config.lightning_node_grpc_tls.unwrap_or(false) {
+        server = server.tls_config(tls::server_tls_config(
+            &Path::new(&config.lightning_data_dir).join("tls"),
+            &config.grpc_tls_hostnames(),
+            config.lightning_node_grpc_tls_client_ca.as_deref(),
+        )?)?;
+    }
+
+    server
         .accept_http1(true)
         .add_service(tonic_web::enable(node_service))
         .serve(addr)
@@ -0,0 +1,138 @@
+use std::{fs, path::Path, str::FromStr};
+
+use anyhow::{bail, Result};
+use ldk_node::bitcoin::Network;
+use log::info;
+
+const NETWORK_FILE_NAME: &str = "network";
+
+pub fn parse_network(network: Option<&str>) -> Result<Network> {
+    match network {
+        None => Ok(Network::Regtest),
+        Some("mainnet") => Ok(Network::Bitcoin),
+        Some(network) => match Network::from_str(&network.to_lowercase()) {
+            Ok(network) => Ok(network),
+            Err(_) => bail!("Unknown bitcoin network \"{}\"", network),
+        },
+    }
+}
+
+pub fn default_esplora_server_url(network: Network) -> Option<&'static str> {
+    match network {
+        Network::Bitcoin => Some("https://blockstream.info/api"),
+        Network::Testnet => Some("https://blockstream.info/testnet/api"),
+        Network::Signet => Some("https://mempool.space/signet/api"),
+        _ => None,
+    }
+}
+
+pub fn default_bitcoind_rpc_port(network: Network) -> u16 {
+    match network {
+        Network::Bitcoin => 8332,
+        Network::Testnet => 18332,
+        Network::Signet => 38332,
+        _ => 18443,
+    }
+}
+
+pub fn default_rgs_server_url(network: Network) -> Option<&'static str> {
+    match network {
+        Network::Bitcoin => Some("https://rapidsync.lightningdevkit.org/snapshot"),
+        Network::Testnet => Some("https://rapidsync.lightningdevkit.org/testnet/snapshot"),
+        _ => None,
+    }
+}
+
+/// Records the network on the first start and refuses to open storage created for another one.
+pub fn ensure_storage_network(data_dir: &str, network: Network) -> Result<()> {
+    let path = Path::new(data_dir).join(NETWORK_FILE_NAME);
+
+    if path.exists() {
+        let stored = fs::read_to_string(&path)?;
+        let stored = stored.trim();
+        if stored != network.to_string() {
+            bail!(
+                "Data dir {} was created for {}, refusing to start on {}",
+                data_dir,
+                stored,
+                network
+            );
+        }
+        return Ok(());
+    }
+
+    // Data dirs created before the network was configurable are always regtest
+    let has_data = fs::read_dir(data_dir)
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false);
+    if has_data && network != Network::Regtest {
+        bail!(
+            "Data dir {} has no network record and was created for regtest, refusing to start on {}",
+            data_dir,
+            network
+        );
+    }
+
+    fs::create_dir_all(data_dir)?;
+    fs::write(&path, network.to_string())?;
+    info!("Data dir {} initialized for {}", data_dir, network);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use ldk_node::bitcoin::Network;
+    use uuid::Uuid;
+
+    use super::{ensure_storage_network, parse_network, NETWORK_FILE_NAME};
+
+    fn data_dir() -> String {
+        let data_dir = env::temp_dir().join(format!("network-{}", Uuid::new_v4()));
+        data_dir.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_parse_network() {
+        assert_eq!(parse_network(None).unwrap(), Network::Regtest);
+        assert_eq!(parse_network(Some("mainnet")).unwrap(), Network::Bitcoin);
+        assert_eq!(parse_network(Some("bitcoin")).unwrap(), Network::Bitcoin);
+        assert_eq!(parse_network(Some("Testnet")).unwrap(), Network::Testnet);
+        assert_eq!(parse_network(Some("signet")).unwrap(), Network::Signet);
+        assert_eq!(parse_network(Some("REGTEST")).unwrap(), Network::Regtest);
+    }
+
+    #[test]
+    fn test_parse_network_rejects_unknown() {
+        assert!(parse_network(Some("")).is_err());
+        assert!(parse_network(Some("testnet4")).is_err());
+        assert!(parse_network(Some("liquid")).is_err());
+    }
+
+    #[test]
+    fn test_storage_network_mismatch() {
+        let data_dir = data_dir();
+
+        ensure_storage_network(&data_dir, Network::Bitcoin).unwrap();
+        ensure_storage_network(&data_dir, Network::Bitcoin).unwrap();
+
+        let err = ensure_storage_network(&data_dir, Network::Testnet).unwrap_err();
+        assert!(err.to_string().contains("created for bitcoin"));
+    }
+
+    #[test]
+    fn test_unrecorded_storage_is_regtest() {
+        let data_dir = data_dir();
+        fs::create_dir_all(&data_dir).unwrap();
+        fs::write(format!("{}/ldk_node_data.sqlite", data_dir), b"").unwrap();
+
+        assert!(ensure_storage_network(&data_dir, Network::Testnet).is_err());
+        ensure_storage_network(&data_dir, Network::Regtest).unwrap();
+        assert_eq!(
+            fs::read_to_string(format!("{}/{}", data_dir, NETWORK_FILE_NAME)).unwrap(),
+            "regtest"
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use ldk_node::{
+    lightning::{ln::ChannelId, util::config::MaxDustHTLCExposure},
+    ChannelConfig,
+};
+
+/// Forwarding policy of a channel, `None` fields are left as they are.
+#[derive(Debug, Clone, Default)]
+pub struct ChannelPolicy {
+    pub forwarding_fee_base_msat: Option<u32>,
+    pub forwarding_fee_proportional_millionths: Option<u32>,
+    pub cltv_expiry_delta: Option<u16>,
+    pub max_dust_htlc_exposure: Option<MaxDustHTLCExposure>,
+}
+
+impl ChannelPolicy {
+    pub fn from_config(config: &ChannelConfig) -> Self {
+        Self {
+            forwarding_fee_base_msat: Some(config.forwarding_fee_base_msat()),
+            forwarding_fee_proportional_millionths: Some(
+                config.forwarding_fee_proportional_millionths(),
+            ),
+            cltv_expiry_delta: Some(config.cltv_expiry_delta()),
+            max_dust_htlc_exposure: Some(config.max_dust_htlc_exposure()),
+        }
+    }
+
+    pub fn apply(&self, config: &ChannelConfig) {
+        if let Some(base_msat) = self.forwarding_fee_base_msat {
+            config.set_forwarding_fee_base_msat(base_msat);
+        }
+        if let Some(proportional_millionths) = self.forwarding_fee_proportional_millionths {
+            config.set_forwarding_fee_proportional_millionths(proportional_millionths);
+        }
+        if let Some(cltv_expiry_delta) = self.cltv_expiry_delta {
+            config.set_cltv_expiry_delta(cltv_expiry_delta);
+        }
+        match self.max_dust_htlc_exposure {
+            Some(MaxDustHTLCExposure::FixedLimitMsat(limit_msat)) => {
+                config.set_max_dust_htlc_exposure_from_fixed_limit(limit_msat)
+            }
+            Some(MaxDustHTLCExposure::FeeRateMultiplier(multiplier)) => {
+                config.set_max_dust_htlc_exposure_from_fee_rate_multiplier(multiplier)
+            }
+            None => {}
+        }
+    }
+}
+
+/// Outcome of updating the policy of several channels, each channel is updated on its own.
+#[derive(Debug, Default)]
+pub struct ChannelPolicyUpdate {
+    pub updated: Vec<ChannelId>,
+    /// Channels which kept their previous policy, with the reason
+    pub failed: Vec<(ChannelId, String)>,
+}
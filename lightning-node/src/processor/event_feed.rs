@@ -0,0 +1,141 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use anyhow::{bail, Result};
+use events::messages::LightningNodeEvent;
+use tokio::sync::broadcast;
+
+const EVENT_FEED_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct FeedEvent {
+    /// Outbox sequence number of the event, used as the resume cursor.
+    pub sequence: u64,
+    pub event: LightningNodeEvent,
+}
+
+/// In-process fan-out of node events for gRPC subscribers, keeps the latest events for resuming.
+///
+/// Only events published by this process can be replayed, cursors of events from before a
+/// restart are rejected like cursors of events which dropped out of the history.
+pub struct EventFeed {
+    sender: broadcast::Sender<FeedEvent>,
+    history: Mutex<VecDeque<FeedEvent>>,
+    /// Sequence of the first event this process publishes
+    first_sequence: u64,
+}
+
+impl EventFeed {
+    pub fn new(first_sequence: u64) -> Self {
+        let (sender, _) = broadcast::channel(EVENT_FEED_CAPACITY);
+
+        Self {
+            sender,
+            history: Mutex::new(VecDeque::with_capacity(EVENT_FEED_CAPACITY)),
+            first_sequence,
+        }
+    }
+
+    pub fn publish(&self, sequence: u64, event: LightningNodeEvent) {
+        let feed_event = FeedEvent { sequence, event };
+
+        let mut history = self.history.lock().unwrap();
+        if history.len() == EVENT_FEED_CAPACITY {
+            history.pop_front();
+        }
+        history.push_back(feed_event.clone());
+
+        // No receivers is fine, nobody is subscribed
+        self.sender.send(feed_event).ok();
+    }
+
+    /// Returns buffered events after the cursor and a receiver for everything newer.
+    ///
+    /// Fails when events after the cursor are no longer buffered.
+    pub fn subscribe(
+        &self,
+        cursor: Option<u64>,
+    ) -> Result<(Vec<FeedEvent>, broadcast::Receiver<FeedEvent>)> {
+        // Holding the history lock keeps publishers out, so nothing is missed or sent twice
+        let history = self.history.lock().unwrap();
+        let receiver = self.sender.subscribe();
+
+        let replay = match cursor {
+            Some(cursor) => {
+                // Nothing was dropped from the history while it's empty
+                let oldest = history
+                    .front()
+                    .map(|e| e.sequence)
+                    .unwrap_or(self.first_sequence);
+                if cursor + 1 < oldest {
+                    bail!(
+                        "Events after cursor {} are no longer available, oldest cursor is {}",
+                        cursor,
+                        oldest - 1
+                    );
+                }
+                history
+                    .iter()
+                    .filter(|e| e.sequence > cursor)
+                    .cloned()
+                    .collect()
+            }
+            None => vec![],
+        };
+
+        Ok((replay, receiver))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use events::messages::LightningNodeEvent;
+
+    use super::{EventFeed, EVENT_FEED_CAPACITY};
+
+    fn event() -> LightningNodeEvent {
+        LightningNodeEvent::PaymentFailed {
+            payment_hash: "hash".to_string(),
+            reason: None,
+        }
+    }
+
+    fn sequences(feed: &EventFeed, cursor: Option<u64>) -> Vec<u64> {
+        let (replay, _) = feed.subscribe(cursor).unwrap();
+        replay.into_iter().map(|e| e.sequence).collect()
+    }
+
+    #[test]
+    fn test_replays_events_after_cursor() {
+        let feed = EventFeed::new(10);
+        for sequence in 10..13 {
+            feed.publish(sequence, event());
+        }
+
+        assert_eq!(sequences(&feed, None), Vec::<u64>::new());
+        assert_eq!(sequences(&feed, Some(9)), vec![10, 11, 12]);
+        assert_eq!(sequences(&feed, Some(11)), vec![12]);
+        assert_eq!(sequences(&feed, Some(12)), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn test_rejects_cursor_from_before_restart() {
+        let feed = EventFeed::new(10);
+
+        assert!(feed.subscribe(Some(9)).is_ok());
+        assert!(feed.subscribe(Some(8)).is_err());
+
+        feed.publish(10, event());
+        assert!(feed.subscribe(Some(8)).is_err());
+    }
+
+    #[test]
+    fn test_rejects_cursor_dropped_from_history() {
+        let feed = EventFeed::new(1);
+        for sequence in 1..=EVENT_FEED_CAPACITY as u64 + 1 {
+            feed.publish(sequence, event());
+        }
+
+        assert!(feed.subscribe(Some(0)).is_err());
+        assert_eq!(sequences(&feed, Some(1)).len(), EVENT_FEED_CAPACITY);
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Result;
+use ldk_node::lightning::ln::PaymentHash;
+use serde::{Deserialize, Serialize};
+
+const HELD_PAYMENTS_FILE_NAME: &str = "held_payments.json";
+
+/// Blocks before the claim deadline at which a held payment is cancelled automatically.
+pub const HOLD_INVOICE_CANCEL_MARGIN_BLOCKS: u32 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HeldPayment {
+    pub amount_msat: u64,
+    pub claim_deadline: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredHeldPayment {
+    payment_hash: [u8; 32],
+    payment: HeldPayment,
+}
+
+/// Hold invoice payments that arrived and wait to be settled or cancelled.
+///
+/// Persisted in the data dir, LDK hands out `PaymentClaimable` only once, so payments held
+/// before a restart can't be settled or cancelled otherwise.
+pub struct HeldPayments {
+    path: PathBuf,
+    payments: Mutex<HashMap<PaymentHash, HeldPayment>>,
+}
+
+impl HeldPayments {
+    pub fn open(data_dir: &str) -> Result<Self> {
+        let path = Path::new(data_dir).join(HELD_PAYMENTS_FILE_NAME);
+        let payments: Vec<StoredHeldPayment> = match fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(_) => vec![],
+        };
+
+        Ok(Self {
+            path,
+            payments: Mutex::new(
+                payments
+                    .into_iter()
+                    .map(|p| (PaymentHash(p.payment_hash), p.payment))
+                    .collect(),
+            ),
+        })
+    }
+
+    pub fn insert(&self, payment_hash: PaymentHash, payment: HeldPayment) -> Result<()> {
+        let mut payments = self.payments.lock().unwrap();
+        payments.insert(payment_hash, payment);
+        self.persist(&payments)
+    }
+
+    pub fn get(&self, payment_hash: &PaymentHash) -> Option<HeldPayment> {
+        self.payments.lock().unwrap().get(payment_hash).copied()
+    }
+
+    pub fn remove(&self, payment_hash: &PaymentHash) -> Result<()> {
+        let mut payments = self.payments.lock().unwrap();
+        if payments.remove(payment_hash).is_some() {
+            self.persist(&payments)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns payments whose claim deadline is within the cancel margin of the block height.
+    pub fn expiring(&self, block_height: u32) -> Vec<PaymentHash> {
+        self.payments
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, p)| match p.claim_deadline {
+                Some(deadline) => block_height + HOLD_INVOICE_CANCEL_MARGIN_BLOCKS >= deadline,
+                None => false,
+            })
+            .map(|(payment_hash, _)| *payment_hash)
+            .collect()
+    }
+
+    fn persist(&self, payments: &HashMap<PaymentHash, HeldPayment>) -> Result<()> {
+        let payments: Vec<StoredHeldPayment> = payments
+            .iter()
+            .map(|(payment_hash, payment)| StoredHeldPayment {
+                payment_hash: payment_hash.0,
+                payment: *payment,
+            })
+            .collect();
+        let tmp_path = self.path.with_extension("json.tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&serde_json::to_vec(&payments)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use ldk_node::lightning::ln::PaymentHash;
+    use uuid::Uuid;
+
+    use super::{HeldPayment, HeldPayments};
+
+    #[test]
+    fn test_held_payments_survive_restart() {
+        let data_dir = env::temp_dir().join(format!("held-payments-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let data_dir = data_dir.to_str().unwrap();
+        let payment = HeldPayment {
+            amount_msat: 1_000,
+            claim_deadline: Some(100),
+        };
+
+        let held_payments = HeldPayments::open(data_dir).unwrap();
+        held_payments.insert(PaymentHash([1; 32]), payment).unwrap();
+        held_payments.insert(PaymentHash([2; 32]), payment).unwrap();
+        held_payments.remove(&PaymentHash([2; 32])).unwrap();
+
+        let held_payments = HeldPayments::open(data_dir).unwrap();
+        assert_eq!(held_payments.get(&PaymentHash([1; 32])), Some(payment));
+        assert_eq!(held_payments.get(&PaymentHash([2; 32])), None);
+        assert_eq!(held_payments.expiring(88), vec![PaymentHash([1; 32])]);
+        assert!(held_payments.expiring(87).is_empty());
+    }
+}
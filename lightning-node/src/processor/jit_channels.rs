@@ -0,0 +1,747 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use ldk_node::{
+    bitcoin::secp256k1::PublicKey, io::sqlite_store::SqliteStore,
+    lightning::ln::channelmanager::InterceptId, Event, Node, UserChannelId,
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::LightningNodeConfig, utils::unix_time};
+
+const JIT_CHANNELS_FILE_NAME: &str = "jit_channels.json";
+
+/// How long intercepted parts of a payment wait for the rest of it and for the channel to open.
+pub const JIT_CHANNEL_OPEN_TIMEOUT: Duration = Duration::from_secs(90);
+/// Capacity of a JIT channel as a multiple of the payment, so the client can receive more later.
+const JIT_CHANNEL_CAPACITY_MULTIPLIER: u64 = 2;
+const JIT_CHANNEL_MIN_CAPACITY_SATS: u64 = 100_000;
+
+const DEFAULT_MIN_FEE_MSAT: u64 = 1_000_000;
+const DEFAULT_FEE_PROPORTIONAL_MILLIONTHS: u32 = 10_000;
+const DEFAULT_MIN_PAYMENT_SIZE_MSAT: u64 = 10_000_000;
+const DEFAULT_MAX_PAYMENT_SIZE_MSAT: u64 = 1_000_000_000;
+const DEFAULT_EXPIRY_SECS: u64 = 86_400;
+const DEFAULT_MAX_PENDING_PER_CLIENT: usize = 3;
+
+/// Configured terms JIT channels are sold for.
+#[derive(Debug, Clone, Copy)]
+pub struct JitChannelTerms {
+    min_fee_msat: u64,
+    proportional_millionths: u32,
+    min_payment_size_msat: u64,
+    max_payment_size_msat: u64,
+    expiry_secs: u64,
+    /// Unused SCIDs a client may hold at once
+    pub max_pending_per_client: usize,
+}
+
+impl JitChannelTerms {
+    pub fn from_config(config: &LightningNodeConfig) -> Self {
+        Self {
+            min_fee_msat: config
+                .jit_channel_min_fee_msat
+                .unwrap_or(DEFAULT_MIN_FEE_MSAT),
+            proportional_millionths: config
+                .jit_channel_fee_proportional_millionths
+                .unwrap_or(DEFAULT_FEE_PROPORTIONAL_MILLIONTHS),
+            min_payment_size_msat: config
+                .jit_channel_min_payment_size_msat
+                .unwrap_or(DEFAULT_MIN_PAYMENT_SIZE_MSAT),
+            max_payment_size_msat: config
+                .jit_channel_max_payment_size_msat
+                .unwrap_or(DEFAULT_MAX_PAYMENT_SIZE_MSAT),
+            expiry_secs: config
+                .jit_channel_expiry_secs
+                .unwrap_or(DEFAULT_EXPIRY_SECS),
+            max_pending_per_client: config
+                .jit_channel_max_pending_per_client
+                .unwrap_or(DEFAULT_MAX_PENDING_PER_CLIENT),
+        }
+    }
+
+    /// Fee params offered for a channel sold now.
+    pub fn fee_params(&self) -> OpeningFeeParams {
+        OpeningFeeParams {
+            min_fee_msat: self.min_fee_msat,
+            proportional_millionths: self.proportional_millionths,
+            min_payment_size_msat: self.min_payment_size_msat,
+            max_payment_size_msat: self.max_payment_size_msat,
+            valid_until: unix_time() + self.expiry_secs,
+        }
+    }
+}
+
+/// Opening fee terms of a JIT channel, the `opening_fee_params` of LSPS2.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct OpeningFeeParams {
+    pub min_fee_msat: u64,
+    pub proportional_millionths: u32,
+    pub min_payment_size_msat: u64,
+    pub max_payment_size_msat: u64,
+    /// Unix timestamp after which payments to the intercept SCID are rejected
+    pub valid_until: u64,
+}
+
+impl OpeningFeeParams {
+    /// Fee for opening a channel for the payment, rounded up as LSPS2 requires.
+    ///
+    /// `None` if the payment is out of the accepted range or doesn't cover the fee.
+    pub fn opening_fee(&self, payment_size_msat: u64) -> Option<u64> {
+        if payment_size_msat < self.min_payment_size_msat
+            || payment_size_msat > self.max_payment_size_msat
+        {
+            return None;
+        }
+
+        let proportional_fee = (payment_size_msat as u128 * self.proportional_millionths as u128)
+            .div_ceil(1_000_000) as u64;
+        let fee = proportional_fee.max(self.min_fee_msat);
+
+        if fee >= payment_size_msat {
+            return None;
+        }
+        Some(fee)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum JitChannelState {
+    /// Waiting for the payment
+    Pending,
+    /// Payment arrived and the channel is being opened
+    Opening { user_channel_id: u128 },
+    /// Payment was forwarded over the new channel, the SCID isn't used anymore
+    Completed,
+}
+
+/// The client holds as many unused intercept SCIDs as allowed.
+#[derive(Debug)]
+pub struct JitChannelLimitReached;
+
+impl fmt::Display for JitChannelLimitReached {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Too many pending JIT channels for the client")
+    }
+}
+
+impl std::error::Error for JitChannelLimitReached {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JitChannel {
+    pub intercept_scid: u64,
+    pub client_node_id: String,
+    /// Expected size of the payment, `None` if the client's invoice has no amount
+    pub payment_size_msat: Option<u64>,
+    pub fee_params: OpeningFeeParams,
+    pub state: JitChannelState,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct InterceptedHtlc {
+    pub intercept_id: InterceptId,
+    pub expected_outbound_amount_msat: u64,
+    pub intercepted_at: Instant,
+}
+
+/// What to do with the HTLCs of a payment once its channel is ready.
+#[derive(Debug, PartialEq)]
+pub enum ForwardAction {
+    /// Forward every HTLC with the given amount, the opening fee is deducted
+    Forward(Vec<(InterceptId, u64)>),
+    /// The fee can't be deducted from the parts, fail all of them
+    Fail(Vec<InterceptId>),
+}
+
+/// What to do with an intercepted HTLC.
+#[derive(Debug)]
+pub enum InterceptAction {
+    /// Fail the listed HTLCs back to the sender
+    Fail(Vec<InterceptId>),
+    /// Hold the HTLC until the rest of the payment arrives
+    Wait,
+    /// The whole payment arrived, open the channel to the client
+    OpenChannel {
+        client_node_id: String,
+        channel_amount_sats: u64,
+    },
+}
+
+/// JIT channels sold to clients, keyed by their intercept SCID.
+///
+/// Channels are persisted in the data dir so handed out SCIDs keep working after a restart.
+/// Intercepted HTLCs are kept in memory only, LDK fails them back before they expire.
+pub struct JitChannels {
+    path: PathBuf,
+    channels: Mutex<HashMap<u64, JitChannel>>,
+    intercepted: Mutex<HashMap<u64, Vec<InterceptedHtlc>>>,
+}
+
+impl JitChannels {
+    pub fn open(data_dir: &str) -> Result<Self> {
+        let path = Path::new(data_dir).join(JIT_CHANNELS_FILE_NAME);
+        let channels: Vec<JitChannel> = match fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(_) => vec![],
+        };
+
+        Ok(Self {
+            path,
+            channels: Mutex::new(
+                channels
+                    .into_iter()
+                    .map(|c| (c.intercept_scid, c))
+                    .collect(),
+            ),
+            intercepted: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Stores a newly sold channel, dropping expired and completed ones first.
+    ///
+    /// Fails with `JitChannelLimitReached` if the client holds `max_pending_per_client` unused
+    /// SCIDs already.
+    pub fn insert(&self, channel: JitChannel, max_pending_per_client: usize) -> Result<()> {
+        let mut channels = self.channels.lock().unwrap();
+        let now = unix_time();
+        channels.retain(|_, c| match c.state {
+            JitChannelState::Pending => now <= c.fee_params.valid_until,
+            JitChannelState::Opening { .. } => true,
+            JitChannelState::Completed => false,
+        });
+
+        let pending = channels
+            .values()
+            .filter(|c| {
+                c.client_node_id == channel.client_node_id && c.state == JitChannelState::Pending
+            })
+            .count();
+        if pending >= max_pending_per_client {
+            // Persist the pruned channels anyway
+            self.persist(&channels)?;
+            bail!(JitChannelLimitReached);
+        }
+
+        channels.insert(channel.intercept_scid, channel);
+        self.persist(&channels)
+    }
+
+    pub fn get(&self, intercept_scid: u64) -> Option<JitChannel> {
+        self.channels.lock().unwrap().get(&intercept_scid).cloned()
+    }
+
+    /// Records an intercepted HTLC and decides whether the channel can be opened.
+    pub fn intercept(&self, intercept_scid: u64, htlc: InterceptedHtlc) -> InterceptAction {
+        let channel = match self.get(intercept_scid) {
+            Some(channel)
+                if channel.state == JitChannelState::Pending
+                    && unix_time() <= channel.fee_params.valid_until =>
+            {
+                channel
+            }
+            _ => return InterceptAction::Fail(vec![htlc.intercept_id]),
+        };
+
+        let mut intercepted = self.intercepted.lock().unwrap();
+        let htlcs = intercepted.entry(intercept_scid).or_default();
+        htlcs.push(htlc);
+
+        let received_msat: u64 = htlcs.iter().map(|h| h.expected_outbound_amount_msat).sum();
+        let payment_size_msat = match channel.payment_size_msat {
+            // Parts of a multi-part payment are collected until the expected amount arrived
+            Some(payment_size_msat) if received_msat < payment_size_msat => {
+                return InterceptAction::Wait
+            }
+            Some(payment_size_msat) => payment_size_msat,
+            None => received_msat,
+        };
+
+        if channel.fee_params.opening_fee(payment_size_msat).is_none() {
+            let failed = htlcs.iter().map(|h| h.intercept_id).collect();
+            intercepted.remove(&intercept_scid);
+            return InterceptAction::Fail(failed);
+        }
+
+        InterceptAction::OpenChannel {
+            client_node_id: channel.client_node_id,
+            channel_amount_sats: (payment_size_msat.div_ceil(1000)
+                * JIT_CHANNEL_CAPACITY_MULTIPLIER)
+                .max(JIT_CHANNEL_MIN_CAPACITY_SATS),
+        }
+    }
+
+    pub fn set_state(&self, intercept_scid: u64, state: JitChannelState) -> Result<()> {
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(channel) = channels.get_mut(&intercept_scid) {
+            channel.state = state;
+        }
+        self.persist(&channels)
+    }
+
+    /// Returns the SCID of the channel being opened with the user channel id.
+    pub fn opening(&self, user_channel_id: u128) -> Option<u64> {
+        self.channels
+            .lock()
+            .unwrap()
+            .values()
+            .find(|c| c.state == JitChannelState::Opening { user_channel_id })
+            .map(|c| c.intercept_scid)
+    }
+
+    /// Removes the intercepted HTLCs of the SCID and splits the opening fee between them.
+    ///
+    /// The fee is taken from the parts in the order they arrived, as LSPS2 describes. A part
+    /// keeps at least 1 msat to forward and what it can't cover is taken from the next parts. If
+    /// all parts together can't cover the fee the whole payment is failed.
+    pub fn take_forwards(&self, intercept_scid: u64) -> ForwardAction {
+        let htlcs = self
+            .intercepted
+            .lock()
+            .unwrap()
+            .remove(&intercept_scid)
+            .unwrap_or_default();
+        let failed = || htlcs.iter().map(|h| h.intercept_id).collect();
+
+        let channel = match self.get(intercept_scid) {
+            Some(channel) => channel,
+            None => return ForwardAction::Fail(failed()),
+        };
+        let received_msat: u64 = htlcs.iter().map(|h| h.expected_outbound_amount_msat).sum();
+        let payment_size_msat = channel.payment_size_msat.unwrap_or(received_msat);
+        let mut remaining_fee_msat = match channel.fee_params.opening_fee(payment_size_msat) {
+            Some(fee_msat) => fee_msat,
+            None => return ForwardAction::Fail(failed()),
+        };
+
+        let mut forwards = vec![];
+        for htlc in &htlcs {
+            let fee_msat = remaining_fee_msat.min(htlc.expected_outbound_amount_msat - 1);
+            forwards.push((
+                htlc.intercept_id,
+                htlc.expected_outbound_amount_msat - fee_msat,
+            ));
+            remaining_fee_msat -= fee_msat;
+        }
+
+        if remaining_fee_msat > 0 {
+            return ForwardAction::Fail(failed());
+        }
+        ForwardAction::Forward(forwards)
+    }
+
+    /// Removes the intercepted HTLCs of the SCID, returning them to be failed.
+    pub fn take_failed(&self, intercept_scid: u64) -> Vec<InterceptId> {
+        self.intercepted
+            .lock()
+            .unwrap()
+            .remove(&intercept_scid)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|h| h.intercept_id)
+            .collect()
+    }
+
+    /// Returns SCIDs whose first HTLC waits longer than `JIT_CHANNEL_OPEN_TIMEOUT`.
+    pub fn timed_out(&self) -> Vec<u64> {
+        self.intercepted
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, htlcs)| match htlcs.first() {
+                Some(htlc) => htlc.intercepted_at.elapsed() > JIT_CHANNEL_OPEN_TIMEOUT,
+                None => false,
+            })
+            .map(|(intercept_scid, _)| *intercept_scid)
+            .collect()
+    }
+
+    /// Opens channels for intercepted payments and forwards them once the channels are ready.
+    pub fn handle_event(&self, node: &Node<SqliteStore>, event: &Event) {
+        match event {
+            Event::HTLCIntercepted {
+                intercept_id,
+                requested_next_hop_scid,
+                payment_hash,
+                expected_outbound_amount_msat,
+                ..
+            } => {
+                let htlc = InterceptedHtlc {
+                    intercept_id: *intercept_id,
+                    expected_outbound_amount_msat: *expected_outbound_amount_msat,
+                    intercepted_at: Instant::now(),
+                };
+
+                match self.intercept(*requested_next_hop_scid, htlc) {
+                    InterceptAction::Fail(intercept_ids) => {
+                        warn!(
+                            "Rejecting intercepted payment {} for SCID {}",
+                            payment_hash, requested_next_hop_scid
+                        );
+                        fail_htlcs(node, &intercept_ids);
+                    }
+                    InterceptAction::Wait => {}
+                    InterceptAction::OpenChannel {
+                        client_node_id,
+                        channel_amount_sats,
+                    } => match open_channel(node, &client_node_id, channel_amount_sats) {
+                        Ok(user_channel_id) => {
+                            info!(
+                                "Opening JIT channel of {} sats to {} for payment {}",
+                                channel_amount_sats, client_node_id, payment_hash
+                            );
+                            self.set_state_logged(
+                                *requested_next_hop_scid,
+                                JitChannelState::Opening {
+                                    user_channel_id: user_channel_id.0,
+                                },
+                            );
+                        }
+                        Err(err) => {
+                            error!("Cannot open JIT channel to {}: {}", client_node_id, err);
+                            fail_htlcs(node, &self.take_failed(*requested_next_hop_scid));
+                        }
+                    },
+                }
+            }
+            Event::ChannelReady {
+                channel_id,
+                user_channel_id,
+                counterparty_node_id,
+            } => {
+                let intercept_scid = match self.opening(user_channel_id.0) {
+                    Some(intercept_scid) => intercept_scid,
+                    None => return,
+                };
+                let counterparty_node_id = match counterparty_node_id {
+                    Some(node_id) => *node_id,
+                    None => match self
+                        .get(intercept_scid)
+                        .and_then(|c| PublicKey::from_str(&c.client_node_id).ok())
+                    {
+                        Some(node_id) => node_id,
+                        None => return,
+                    },
+                };
+
+                match self.take_forwards(intercept_scid) {
+                    ForwardAction::Forward(forwards) => {
+                        for (intercept_id, amount_msat) in forwards {
+                            if let Err(err) = node.forward_intercepted_htlc(
+                                intercept_id,
+                                channel_id,
+                                counterparty_node_id,
+                                amount_msat,
+                            ) {
+                                error!(
+                                    "Cannot forward payment over JIT channel {}: {}",
+                                    channel_id, err
+                                );
+                            }
+                        }
+                    }
+                    ForwardAction::Fail(intercept_ids) => {
+                        error!(
+                            "Payment parts don't cover the opening fee of JIT channel {}",
+                            channel_id
+                        );
+                        fail_htlcs(node, &intercept_ids);
+                    }
+                }
+                self.set_state_logged(intercept_scid, JitChannelState::Completed);
+            }
+            Event::ChannelClosed {
+                user_channel_id, ..
+            } => {
+                // The client may retry the payment once the channel failed to open
+                if let Some(intercept_scid) = self.opening(user_channel_id.0) {
+                    fail_htlcs(node, &self.take_failed(intercept_scid));
+                    self.set_state_logged(intercept_scid, JitChannelState::Pending);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Fails the HTLCs of a payment which didn't complete in time.
+    ///
+    /// A channel still being opened for it is closed, so the client can't receive it later for
+    /// free, and the SCID accepts the payment again until it expires.
+    pub fn abandon(&self, node: &Node<SqliteStore>, intercept_scid: u64) {
+        fail_htlcs(node, &self.take_failed(intercept_scid));
+
+        let user_channel_id = match self.get(intercept_scid).map(|c| c.state) {
+            Some(JitChannelState::Opening { user_channel_id }) => UserChannelId(user_channel_id),
+            _ => return,
+        };
+        self.set_state_logged(intercept_scid, JitChannelState::Pending);
+
+        let channel = node
+            .list_channels()
+            .into_iter()
+            .find(|c| c.user_channel_id == user_channel_id);
+        if let Some(channel) = channel {
+            warn!(
+                "Closing JIT channel {} which didn't become ready in time",
+                channel.channel_id
+            );
+            let closed = node
+                .close_channel(&user_channel_id, channel.counterparty_node_id)
+                .or_else(|_| {
+                    node.force_close_channel(&user_channel_id, channel.counterparty_node_id)
+                });
+            if let Err(err) = closed {
+                error!("Cannot close JIT channel {}: {}", channel.channel_id, err);
+            }
+        }
+    }
+
+    fn set_state_logged(&self, intercept_scid: u64, state: JitChannelState) {
+        if let Err(err) = self.set_state(intercept_scid, state) {
+            error!(
+                "Cannot store state of JIT channel {}: {}",
+                intercept_scid, err
+            );
+        }
+    }
+
+    /// Writes to a temporary file first so a crash never leaves a partial file behind.
+    fn persist(&self, channels: &HashMap<u64, JitChannel>) -> Result<()> {
+        let channels: Vec<&JitChannel> = channels.values().collect();
+        let tmp_path = self.path.with_extension("json.tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&serde_json::to_vec(&channels)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        Ok(())
+    }
+}
+
+/// Opens an unannounced channel to the client, who has to be connected when the payment arrives.
+///
+/// This is a regular channel, the payment is forwarded once `ChannelReady` arrives for it. The
+/// accepting side decides how many confirmations a channel needs, so it's only ready without
+/// waiting for confirmations if the client accepts zero-conf channels from this node, with
+/// ldk-node by listing it in `trusted_peers_0conf`. Otherwise the payment times out before the
+/// channel is ready.
+fn open_channel(
+    node: &Node<SqliteStore>,
+    client_node_id: &str,
+    channel_amount_sats: u64,
+) -> Result<UserChannelId> {
+    let client_node_id = PublicKey::from_str(client_node_id)?;
+    let address = match node
+        .list_peers()
+        .into_iter()
+        .find(|p| p.node_id == client_node_id && p.is_connected)
+    {
+        Some(peer) => peer.address,
+        None => bail!("Client {} is not connected", client_node_id),
+    };
+
+    Ok(node.connect_open_channel(
+        client_node_id,
+        address,
+        channel_amount_sats,
+        None,
+        None,
+        false,
+    )?)
+}
+
+fn fail_htlcs(node: &Node<SqliteStore>, intercept_ids: &[InterceptId]) {
+    for intercept_id in intercept_ids {
+        if let Err(err) = node.fail_intercepted_htlc(*intercept_id) {
+            error!("Cannot fail intercepted HTLC: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, time::Instant};
+
+    use ldk_node::lightning::ln::channelmanager::InterceptId;
+    use uuid::Uuid;
+
+    use crate::utils::unix_time;
+
+    use super::{
+        ForwardAction, InterceptAction, InterceptedHtlc, JitChannel, JitChannelLimitReached,
+        JitChannelState, JitChannels, OpeningFeeParams,
+    };
+
+    const SCID: u64 = 42;
+
+    fn fee_params() -> OpeningFeeParams {
+        OpeningFeeParams {
+            min_fee_msat: 1_000,
+            proportional_millionths: 10_000,
+            min_payment_size_msat: 10_000,
+            max_payment_size_msat: 1_000_000,
+            valid_until: unix_time() + 60,
+        }
+    }
+
+    fn jit_channels(payment_size_msat: Option<u64>) -> JitChannels {
+        let data_dir = env::temp_dir().join(format!("jit-channels-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let jit_channels = JitChannels::open(data_dir.to_str().unwrap()).unwrap();
+        jit_channels
+            .insert(
+                JitChannel {
+                    intercept_scid: SCID,
+                    client_node_id: "client".to_string(),
+                    payment_size_msat,
+                    fee_params: fee_params(),
+                    state: JitChannelState::Pending,
+                },
+                1,
+            )
+            .unwrap();
+        jit_channels
+    }
+
+    fn intercept(jit_channels: &JitChannels, id: u8, amount_msat: u64) -> InterceptAction {
+        jit_channels.intercept(
+            SCID,
+            InterceptedHtlc {
+                intercept_id: InterceptId([id; 32]),
+                expected_outbound_amount_msat: amount_msat,
+                intercepted_at: Instant::now(),
+            },
+        )
+    }
+
+    #[test]
+    fn test_opening_fee() {
+        let fee_params = fee_params();
+
+        // The minimum fee applies to small payments, the proportional one rounds up
+        assert_eq!(fee_params.opening_fee(10_000), Some(1_000));
+        assert_eq!(fee_params.opening_fee(200_001), Some(2_001));
+        assert_eq!(fee_params.opening_fee(1_000_000), Some(10_000));
+        assert_eq!(fee_params.opening_fee(9_999), None);
+        assert_eq!(fee_params.opening_fee(1_000_001), None);
+
+        let fee_params = OpeningFeeParams {
+            min_fee_msat: 20_000,
+            ..fee_params
+        };
+        assert_eq!(fee_params.opening_fee(20_000), None);
+    }
+
+    #[test]
+    fn test_take_forwards_deducts_fee() {
+        let jit_channels = jit_channels(Some(100_000));
+
+        assert!(matches!(
+            intercept(&jit_channels, 1, 60_000),
+            InterceptAction::Wait
+        ));
+        assert!(matches!(
+            intercept(&jit_channels, 2, 40_000),
+            InterceptAction::OpenChannel { .. }
+        ));
+
+        assert_eq!(
+            jit_channels.take_forwards(SCID),
+            ForwardAction::Forward(vec![
+                (InterceptId([1; 32]), 59_000),
+                (InterceptId([2; 32]), 40_000),
+            ])
+        );
+        assert!(jit_channels.take_failed(SCID).is_empty());
+    }
+
+    #[test]
+    fn test_take_forwards_spreads_fee_over_parts() {
+        let jit_channels = jit_channels(Some(100_000));
+
+        intercept(&jit_channels, 1, 400);
+        intercept(&jit_channels, 2, 500);
+        intercept(&jit_channels, 3, 99_100);
+
+        // Each part keeps 1 msat, the rest of the fee is taken from the next part
+        assert_eq!(
+            jit_channels.take_forwards(SCID),
+            ForwardAction::Forward(vec![
+                (InterceptId([1; 32]), 1),
+                (InterceptId([2; 32]), 1),
+                (InterceptId([3; 32]), 98_998),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_take_forwards_fails_when_parts_cant_cover_fee() {
+        let jit_channels = jit_channels(Some(10_000));
+        jit_channels
+            .channels
+            .lock()
+            .unwrap()
+            .get_mut(&SCID)
+            .unwrap()
+            .fee_params
+            .min_fee_msat = 9_999;
+
+        intercept(&jit_channels, 1, 5_000);
+        assert!(matches!(
+            intercept(&jit_channels, 2, 5_000),
+            InterceptAction::OpenChannel { .. }
+        ));
+
+        // Each part has to keep 1 msat, which leaves 9_998 msat for the fee
+        assert_eq!(
+            jit_channels.take_forwards(SCID),
+            ForwardAction::Fail(vec![InterceptId([1; 32]), InterceptId([2; 32])])
+        );
+    }
+
+    #[test]
+    fn test_insert_limits_pending_channels() {
+        let jit_channels = jit_channels(None);
+
+        let err = jit_channels
+            .insert(
+                JitChannel {
+                    intercept_scid: SCID + 1,
+                    client_node_id: "client".to_string(),
+                    payment_size_msat: None,
+                    fee_params: fee_params(),
+                    state: JitChannelState::Pending,
+                },
+                1,
+            )
+            .unwrap_err();
+        assert!(err.is::<JitChannelLimitReached>());
+
+        // Completed channels are pruned and don't count
+        jit_channels
+            .set_state(SCID, JitChannelState::Completed)
+            .unwrap();
+        jit_channels
+            .insert(
+                JitChannel {
+                    intercept_scid: SCID + 1,
+                    client_node_id: "client".to_string(),
+                    payment_size_msat: None,
+                    fee_params: fee_params(),
+                    state: JitChannelState::Pending,
+                },
+                1,
+            )
+            .unwrap();
+        assert!(jit_channels.get(SCID).is_none());
+    }
+}
@@ -2,71 +2,221 @@ use std::{str::FromStr, sync::Arc, time::Duration};
 
 use anyhow::{bail, Error, Result};
 use ldk_node::{
+    bdk::{LocalUtxo, TransactionDetails},
     bip39::Mnemonic,
     bitcoin::{
-        hashes::Hash,
+        hashes::{sha256, Hash},
         secp256k1::{
             rand::{rngs::OsRng, RngCore},
             PublicKey,
         },
-        Address, Network,
+        Address, Network, Txid,
     },
     io::sqlite_store::SqliteStore,
-    lightning::ln::{msgs::SocketAddress, ChannelId, PaymentHash},
+    lightning::{
+        chain::chaininterface::ConfirmationTarget,
+        ln::{
+            channelmanager::PaymentId, msgs::SocketAddress, ChannelId, PaymentHash, PaymentPreimage,
+        },
+        offers::{invoice::Bolt12Invoice, offer::Offer, refund::Refund},
+        util::message_signing,
+    },
     lightning_invoice::Bolt11Invoice,
-    Builder, ChannelConfig, ChannelDetails, Event, Node, NodeError, PeerDetails, UserChannelId,
+    Builder, ChannelConfig, ChannelDetails, Event, Node, NodeError, PaymentDetails,
+    PaymentDirection, PaymentStatus, PeerDetails, UserChannelId,
+};
+use log::{error, info, warn};
+use tokio::{
+    sync::{broadcast, Notify},
+    time::sleep,
 };
-use tokio::{sync::Mutex, time::sleep};
 
 use events::{
-    config::RabbitMqConfig, lightning_node_events::LightningNodeEvents,
-    messages::LightningNodeEvent,
+    event_bus::EventBus,
+    messages::{EventEnvelope, LightningNodeEvent},
+};
+
+use crate::{
+    config::{ChainSource, GossipSource, LightningNodeConfig},
+    network,
+    utils::unix_time,
+};
+
+pub use self::{
+    channel_policy::ChannelPolicy,
+    jit_channels::{JitChannel, JitChannelLimitReached},
 };
 
-use crate::config::LightningNodeConfig;
+use self::{
+    channel_policy::ChannelPolicyUpdate,
+    event_feed::{EventFeed, FeedEvent},
+    hold_invoices::{HeldPayment, HeldPayments},
+    jit_channels::{JitChannelState, JitChannelTerms, JitChannels},
+    outbox::Outbox,
+    payment_times::PaymentTimes,
+    route_fees::{RouteFeeEstimate, RouteFees},
+};
+
+mod channel_policy;
+mod event_feed;
+mod hold_invoices;
+mod jit_channels;
+mod node_events;
+mod outbox;
+mod payment_times;
+mod route_fees;
+
+const OUTBOX_MIN_RETRY_DELAY: Duration = Duration::from_millis(500);
+const OUTBOX_MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+const PAYMENT_STATUS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const HOLD_INVOICE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const JIT_CHANNEL_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// How long route fee estimates wait for the outcome of their probes.
+const PROBE_OUTCOME_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct SyncStatus {
+    pub is_running: bool,
+    pub chain_source: String,
+    pub gossip_source: String,
+    pub best_block_height: u32,
+    /// Unix timestamps of the last successful syncs, `None` until the first one finished
+    pub latest_lightning_wallet_sync_timestamp: Option<u64>,
+    pub latest_onchain_wallet_sync_timestamp: Option<u64>,
+    pub latest_rgs_snapshot_timestamp: Option<u64>,
+    pub network_graph_nodes: u64,
+    pub network_graph_channels: u64,
+}
 
 pub struct NodeProcessor {
     node: Arc<Node<SqliteStore>>,
-    pub events: Arc<Mutex<LightningNodeEvents>>,
+    network: Network,
+    alias: Option<String>,
+    chain_source: ChainSource,
+    gossip_source: GossipSource,
+    pub events: Arc<dyn EventBus>,
+    outbox: Arc<Outbox>,
+    outbox_notify: Arc<Notify>,
+    event_feed: Arc<EventFeed>,
+    held_payments: Arc<HeldPayments>,
+    payment_times: PaymentTimes,
+    route_fees: Arc<RouteFees>,
+    jit_channels: Arc<JitChannels>,
+    jit_channel_terms: JitChannelTerms,
 }
 
 impl NodeProcessor {
     pub async fn new(
         config: LightningNodeConfig,
-        rabbitmq_config: RabbitMqConfig,
+        events: Arc<dyn EventBus>,
     ) -> Result<Self, Error> {
+        let network = network::parse_network(config.bitcoin_network.as_deref())?;
+        network::ensure_storage_network(&config.lightning_data_dir, network)?;
+        let outbox = Arc::new(Outbox::open(&config.lightning_data_dir)?);
+        let jit_channels = Arc::new(JitChannels::open(&config.lightning_data_dir)?);
+        let payment_times = PaymentTimes::open(&config.lightning_data_dir)?;
+        let held_payments = Arc::new(HeldPayments::open(&config.lightning_data_dir)?);
+
+        let chain_source = config.chain_source(network)?;
+        let gossip_source = config.gossip_source(network)?;
+        info!(
+            "Using {} chain source and {} gossip on {}",
+            chain_source, gossip_source, network
+        );
+
         let mut builder = Builder::new();
-        builder.set_network(Network::Regtest);
+        builder.set_network(network);
         builder.set_storage_dir_path(config.lightning_data_dir.clone());
-        builder.set_log_dir_path(config.lightning_data_dir);
+        builder.set_log_dir_path(config.lightning_data_dir.clone());
         builder.set_log_level(ldk_node::LogLevel::Debug);
         builder.set_accept_forwards_to_private_channels(true);
+        // HTLCs to intercept SCIDs are handed to us to open JIT channels
+        builder.set_accept_intercept_htlcs(true);
         builder.set_listening_addresses(vec![SocketAddress::from_str(format!("0.0.0.0:{}", config.lightning_node_port).as_str()).unwrap()])?;
 
+        if let Some(alias) = &config.lightning_node_alias {
+            builder.set_node_alias(alias.clone())?;
+        }
+
         if config.mnemonic.is_some() {
             let mnemonic = Mnemonic::from_str(&config.mnemonic.unwrap()).unwrap();
             builder.set_entropy_bip39_mnemonic(mnemonic, None);
         }
 
-        builder.set_esplora_server(config.esplora_server_url);
-        builder.set_gossip_source_p2p();
-        //builder.set_gossip_source_rgs(
-        //    "https://rapidsync.lightningdevkit.org/testnet/snapshot".to_string(),
-        //);
+        match &chain_source {
+            ChainSource::Esplora { server_url } => builder.set_esplora_server(server_url.clone()),
+            ChainSource::BitcoindRpc {
+                host,
+                port,
+                user,
+                password,
+            } => builder.set_chain_source_bitcoind_rpc(
+                host.clone(),
+                *port,
+                user.clone(),
+                password.clone(),
+            ),
+        };
+
+        match &gossip_source {
+            GossipSource::P2p => builder.set_gossip_source_p2p(),
+            GossipSource::Rgs { server_url } => builder.set_gossip_source_rgs(server_url.clone()),
+        };
 
         let node = Arc::new(builder.build()?);
-        let events = Arc::new(Mutex::new(
-            LightningNodeEvents::new(rabbitmq_config).await.unwrap(),
-        ));
-        Ok(Self { node, events })
+        let first_sequence = outbox.next_sequence();
+        Ok(Self {
+            node,
+            network,
+            alias: config.lightning_node_alias.clone(),
+            chain_source,
+            gossip_source,
+            events,
+            outbox,
+            outbox_notify: Arc::new(Notify::new()),
+            event_feed: Arc::new(EventFeed::new(first_sequence)),
+            held_payments,
+            payment_times,
+            route_fees: Arc::new(RouteFees::new()),
+            jit_channels,
+            jit_channel_terms: JitChannelTerms::from_config(&config),
+        })
     }
 
     pub fn get_id(&self) -> PublicKey {
         self.node.node_id()
     }
 
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    pub fn alias(&self) -> Option<String> {
+        self.alias.clone()
+    }
+
+    /// Reports how far the wallets and the network graph are synced.
+    pub fn sync_status(&self) -> SyncStatus {
+        let status = self.node.status();
+        let network_graph = self.node.network_graph();
+
+        SyncStatus {
+            is_running: status.is_running,
+            chain_source: self.chain_source.to_string(),
+            gossip_source: self.gossip_source.to_string(),
+            best_block_height: status.current_best_block.height,
+            latest_lightning_wallet_sync_timestamp: status.latest_wallet_sync_timestamp,
+            latest_onchain_wallet_sync_timestamp: status.latest_onchain_wallet_sync_timestamp,
+            latest_rgs_snapshot_timestamp: status.latest_rgs_snapshot_timestamp,
+            network_graph_nodes: network_graph.list_nodes().len() as u64,
+            network_graph_channels: network_graph.list_channels().len() as u64,
+        }
+    }
+
     pub fn start(&self) -> Result<(), Error> {
+        self.publish_outbox();
         self.subscribe_events();
+        self.cancel_expiring_hold_invoices();
+        self.fail_timed_out_jit_payments();
         Ok(self.node.start()?)
     }
 
@@ -83,6 +233,10 @@ impl NodeProcessor {
         Ok(self.node.connect(node_id, address, persist)?)
     }
 
+    pub fn disconnect_peer(&self, node_id: PublicKey) -> Result<()> {
+        Ok(self.node.disconnect(node_id)?)
+    }
+
     pub fn get_peers(&self) -> Vec<PeerDetails> {
         self.node.list_peers()
     }
@@ -94,15 +248,15 @@ impl NodeProcessor {
         channel_amount_sats: u64,
         push_to_counterparty_msat: Option<u64>,
         public: bool,
+        policy: &ChannelPolicy,
     ) -> Result<UserChannelId> {
         let channel_config = Arc::new(ChannelConfig::new());
+        policy.apply(&channel_config);
         let address = if address.is_none() {
-            let peer = self
-                .get_peers()
-                .into_iter()
-                .find(|p| p.node_id == node_id)
-                .expect("Peer is not connected, provide address!");
-            peer.address
+            match self.get_peers().into_iter().find(|p| p.node_id == node_id) {
+                Some(peer) => peer.address,
+                None => bail!("Peer {} is not known, provide address!", node_id),
+            }
         } else {
             address.unwrap()
         };
@@ -117,16 +271,90 @@ impl NodeProcessor {
         )?)
     }
 
-    pub async fn close_channel(&self, channel_id: ChannelId) -> Result<()> {
-        match self
+    /// Updates the policy of one channel, or of all channels if no channel id is given.
+    ///
+    /// Returns the ids of the updated channels.
+    pub fn update_channel_config(
+        &self,
+        channel_id: Option<ChannelId>,
+        policy: &ChannelPolicy,
+    ) -> Result<ChannelPolicyUpdate> {
+        let channels: Vec<ChannelDetails> = self
             .get_channels()
-            .iter()
+            .into_iter()
+            .filter(|c| channel_id.is_none() || Some(c.channel_id) == channel_id)
+            .collect();
+
+        if let Some(channel_id) = channel_id {
+            if channels.is_empty() {
+                bail!("Channel {} not found", channel_id);
+            }
+        }
+
+        let mut update = ChannelPolicyUpdate::default();
+        for channel in channels {
+            // The whole config is replaced, so start from the current one
+            let channel_config = Arc::new(ChannelConfig::new());
+            if let Some(current) = &channel.config {
+                ChannelPolicy::from_config(current).apply(&channel_config);
+                channel_config.set_accept_underpaying_htlcs(current.accept_underpaying_htlcs());
+                channel_config.set_force_close_avoidance_max_fee_satoshis(
+                    current.force_close_avoidance_max_fee_satoshis(),
+                );
+            }
+            policy.apply(&channel_config);
+
+            match self.node.update_channel_config(
+                &channel.user_channel_id,
+                channel.counterparty_node_id,
+                channel_config,
+            ) {
+                Ok(_) => update.updated.push(channel.channel_id),
+                Err(err) if channel_id.is_some() => return Err(err.into()),
+                Err(err) => {
+                    warn!(
+                        "Cannot update config of channel {}: {}",
+                        channel.channel_id, err
+                    );
+                    update.failed.push((channel.channel_id, err.to_string()));
+                }
+            }
+        }
+
+        Ok(update)
+    }
+
+    /// Closes the channel cooperatively, or unilaterally when `force` is set.
+    ///
+    /// A cooperative close can send the funds to `close_to` instead of the on-chain wallet.
+    pub async fn close_channel(
+        &self,
+        channel_id: ChannelId,
+        force: bool,
+        close_to: Option<Address>,
+    ) -> Result<()> {
+        let channel = match self
+            .get_channels()
+            .into_iter()
             .find(|c| c.channel_id == channel_id)
         {
-            Some(channel) => Ok(self
+            Some(channel) => channel,
+            None => bail!(NodeError::ChannelClosingFailed),
+        };
+
+        match (force, close_to) {
+            (true, Some(_)) => bail!("Force closed channels are swept to the on-chain wallet"),
+            (true, None) => Ok(self
+                .node
+                .force_close_channel(&channel.user_channel_id, channel.counterparty_node_id)?),
+            (false, Some(address)) => Ok(self.node.close_channel_with_shutdown_script(
+                &channel.user_channel_id,
+                channel.counterparty_node_id,
+                address.script_pubkey(),
+            )?),
+            (false, None) => Ok(self
                 .node
                 .close_channel(&channel.user_channel_id, channel.counterparty_node_id)?),
-            None => bail!(NodeError::ChannelClosingFailed),
         }
     }
 
@@ -134,86 +362,504 @@ impl NodeProcessor {
         Ok(self.node.new_onchain_address()?)
     }
 
+    /// Returns the spendable and the total on-chain balance in sats.
+    pub fn get_onchain_balance(&self) -> Result<(u64, u64)> {
+        Ok((
+            self.node.spendable_onchain_balance_sats()?,
+            self.node.total_onchain_balance_sats()?,
+        ))
+    }
+
+    /// Sends `amount_sats` to the address, or the whole spendable balance if no amount is given.
+    pub fn send_onchain(&self, address: &Address, amount_sats: Option<u64>) -> Result<Txid> {
+        match amount_sats {
+            Some(amount_sats) => Ok(self.node.send_to_onchain_address(address, amount_sats)?),
+            None => Ok(self.node.send_all_to_onchain_address(address)?),
+        }
+    }
+
+    pub fn list_utxos(&self) -> Result<Vec<LocalUtxo>> {
+        Ok(self.node.list_utxos()?)
+    }
+
+    pub fn list_onchain_transactions(&self) -> Result<Vec<TransactionDetails>> {
+        Ok(self.node.list_onchain_transactions()?)
+    }
+
+    /// Returns the current fee rate estimate for the target in sats per 1000 weight units.
+    pub fn estimate_fee_rate(&self, target: ConfirmationTarget) -> u32 {
+        self.node.estimate_fee_rate(target)
+    }
+
     pub fn create_bolt11_invoice(
         &self,
         amount_msat: Option<u64>,
         description: &str,
         expiry_secs: u32,
     ) -> Result<Bolt11Invoice> {
-        match amount_msat {
-            Some(amount) => Ok(self
+        let invoice = match amount_msat {
+            Some(amount) => self
                 .node
-                .receive_payment(amount, description, expiry_secs)?),
-            None => Ok(self
+                .receive_payment(amount, description, expiry_secs)?,
+            None => self
                 .node
-                .receive_variable_amount_payment(description, expiry_secs)?),
+                .receive_variable_amount_payment(description, expiry_secs)?,
+        };
+        self.record_payment_time(
+            &PaymentHash(invoice.payment_hash().to_byte_array()),
+            unix_time(),
+        );
+
+        Ok(invoice)
+    }
+
+    /// Creates an invoice for a payment hash whose preimage only the caller knows.
+    ///
+    /// Arriving payments are held until `settle_hold_invoice` or `cancel_hold_invoice` is called.
+    pub fn create_hold_invoice(
+        &self,
+        payment_hash: PaymentHash,
+        amount_msat: Option<u64>,
+        description: &str,
+        expiry_secs: u32,
+    ) -> Result<Bolt11Invoice> {
+        let invoice = match amount_msat {
+            Some(amount) => self.node.receive_payment_for_hash(
+                amount,
+                description,
+                expiry_secs,
+                payment_hash,
+            )?,
+            None => self.node.receive_variable_amount_payment_for_hash(
+                description,
+                expiry_secs,
+                payment_hash,
+            )?,
+        };
+        self.record_payment_time(&payment_hash, unix_time());
+
+        Ok(invoice)
+    }
+
+    pub fn settle_hold_invoice(&self, preimage: PaymentPreimage) -> Result<PaymentHash> {
+        let payment_hash = PaymentHash(sha256::Hash::hash(&preimage.0).to_byte_array());
+
+        let held_payment = match self.held_payments.get(&payment_hash) {
+            Some(held_payment) => held_payment,
+            None => bail!("No payment is held for {}", payment_hash),
+        };
+
+        self.node
+            .claim_payment_for_hash(payment_hash, held_payment.amount_msat, preimage)?;
+        self.held_payments.remove(&payment_hash)?;
+
+        Ok(payment_hash)
+    }
+
+    pub fn cancel_hold_invoice(&self, payment_hash: PaymentHash) -> Result<()> {
+        self.node.fail_payment_for_hash(payment_hash)?;
+        self.held_payments.remove(&payment_hash)
+    }
+
+    /// Cancels held payments close to their claim deadline, so the channel isn't force-closed.
+    fn cancel_expiring_hold_invoices(&self) {
+        let node = self.node.clone();
+        let held_payments = self.held_payments.clone();
+        tokio::spawn(async move {
+            loop {
+                sleep(HOLD_INVOICE_CHECK_INTERVAL).await;
+
+                let block_height = node.status().current_best_block.height;
+                for payment_hash in held_payments.expiring(block_height) {
+                    warn!(
+                        "Cancelling hold invoice {} close to its claim deadline",
+                        payment_hash
+                    );
+                    match node.fail_payment_for_hash(payment_hash) {
+                        Ok(_) => {
+                            if let Err(err) = held_payments.remove(&payment_hash) {
+                                error!("Cannot forget hold invoice {}: {}", payment_hash, err);
+                            }
+                        }
+                        Err(err) => error!("Cannot cancel hold invoice {}: {}", payment_hash, err),
+                    }
+                }
+            }
+        });
+    }
+
+    /// Sells a JIT channel to the client, payments to the returned intercept SCID open it.
+    ///
+    /// Without a payment size the channel is opened for the first HTLC, so the client's invoice
+    /// must not be paid in multiple parts.
+    pub fn create_jit_channel(
+        &self,
+        client_node_id: PublicKey,
+        payment_size_msat: Option<u64>,
+    ) -> Result<JitChannel> {
+        let fee_params = self.jit_channel_terms.fee_params();
+        if let Some(payment_size_msat) = payment_size_msat {
+            if fee_params.opening_fee(payment_size_msat).is_none() {
+                bail!(NodeError::InvalidAmount);
+            }
+        }
+
+        let channel = JitChannel {
+            intercept_scid: self.node.intercept_scid(),
+            client_node_id: client_node_id.to_string(),
+            payment_size_msat,
+            fee_params,
+            state: JitChannelState::Pending,
+        };
+        self.jit_channels.insert(
+            channel.clone(),
+            self.jit_channel_terms.max_pending_per_client,
+        )?;
+
+        Ok(channel)
+    }
+
+    /// CLTV delta the client adds to the route hint through the intercept SCID.
+    pub fn jit_channel_cltv_expiry_delta(&self) -> u16 {
+        ChannelConfig::new().cltv_expiry_delta()
+    }
+
+    /// Fails intercepted payments which didn't complete or whose channel didn't open in time.
+    fn fail_timed_out_jit_payments(&self) {
+        let node = self.node.clone();
+        let jit_channels = self.jit_channels.clone();
+        tokio::spawn(async move {
+            loop {
+                sleep(JIT_CHANNEL_CHECK_INTERVAL).await;
+
+                for intercept_scid in jit_channels.timed_out() {
+                    warn!(
+                        "Failing timed out payment to JIT channel {}",
+                        intercept_scid
+                    );
+                    jit_channels.abandon(&node, intercept_scid);
+                }
+            }
+        });
+    }
+
+    pub fn create_bolt12_offer(
+        &self,
+        amount_msat: Option<u64>,
+        description: &str,
+    ) -> Result<Offer> {
+        let bolt12_payment = self.node.bolt12_payment();
+        match amount_msat {
+            Some(amount_msat) => Ok(bolt12_payment.receive(amount_msat, description)?),
+            None => Ok(bolt12_payment.receive_variable_amount(description)?),
         }
     }
 
+    pub fn pay_bolt12_offer(
+        &self,
+        offer: &Offer,
+        amount_msat: Option<u64>,
+        payer_note: Option<String>,
+    ) -> Result<PaymentId> {
+        let bolt12_payment = self.node.bolt12_payment();
+        match amount_msat {
+            Some(amount_msat) => {
+                Ok(bolt12_payment.send_using_amount(offer, payer_note, amount_msat)?)
+            }
+            None => Ok(bolt12_payment.send(offer, payer_note)?),
+        }
+    }
+
+    /// Creates a refund we pay, the node pays the invoice the recipient requests the refund with.
+    pub fn initiate_bolt12_refund(&self, amount_msat: u64, expiry_secs: u32) -> Result<Refund> {
+        Ok(self
+            .node
+            .bolt12_payment()
+            .initiate_refund(amount_msat, expiry_secs)?)
+    }
+
+    /// Requests the payment of a refund owed to us by sending an invoice to the refund's creator.
+    pub fn request_bolt12_refund_payment(&self, refund: &Refund) -> Result<Bolt12Invoice> {
+        let invoice = self.node.bolt12_payment().request_refund_payment(refund)?;
+        self.record_payment_time(&invoice.payment_hash(), unix_time());
+
+        Ok(invoice)
+    }
+
     pub fn pay_invoice(
         &self,
         invoice: &Bolt11Invoice,
         amount_msat: Option<u64>,
     ) -> Result<PaymentHash> {
-        match amount_msat {
-            Some(amount_msat) => match self.node.send_payment_using_amount(invoice, amount_msat) {
-                Ok(res) => Ok(res),
-                Err(err) => Err(err.into()),
-            },
-            None => match self.node.send_payment(invoice) {
-                Ok(res) => Ok(res),
-                Err(err) => Err(err.into()),
-            },
-        }
+        let created_at = unix_time();
+        let payment_hash = match amount_msat {
+            Some(amount_msat) => self.node.send_payment_using_amount(invoice, amount_msat)?,
+            None => self.node.send_payment(invoice)?,
+        };
+        self.record_payment_time(&payment_hash, created_at);
+
+        Ok(payment_hash)
     }
 
+    /// Sends a keysend payment, `custom_tlvs` are attached to the onion as `(type, value)` pairs.
     pub fn send_keysend_payment(
         &self,
         destination: PublicKey,
         amount_msat: u64,
+        custom_tlvs: Vec<(u64, Vec<u8>)>,
     ) -> Result<PaymentHash> {
-        match self
+        let created_at = unix_time();
+        self.node
+            .send_spontaneous_payment_probes(amount_msat, destination)?;
+
+        let payment_hash = if custom_tlvs.is_empty() {
+            self.node
+                .send_spontaneous_payment(amount_msat, destination)?
+        } else {
+            self.node.send_spontaneous_payment_with_custom_tlvs(
+                amount_msat,
+                destination,
+                custom_tlvs,
+            )?
+        };
+        self.record_payment_time(&payment_hash, created_at);
+
+        Ok(payment_hash)
+    }
+
+    /// Probes the route to the invoice payee and estimates the routing fee.
+    pub async fn estimate_invoice_route_fee(
+        &self,
+        invoice: &Bolt11Invoice,
+        amount_msat: Option<u64>,
+    ) -> Result<RouteFeeEstimate> {
+        let destination = invoice.recover_payee_pub_key();
+        let amount = match amount_msat.or(invoice.amount_milli_satoshis()) {
+            Some(amount) => amount,
+            None => bail!("Amount is required for zero amount invoices"),
+        };
+
+        if let Some(estimate) = self.route_fees.cached(destination, amount) {
+            return Ok(estimate);
+        }
+
+        let recorded_probes = self.route_fees.recorded_probes(destination);
+        match amount_msat {
+            Some(amount_msat) => self
+                .node
+                .send_payment_probes_using_amount(invoice, amount_msat)?,
+            None => self.node.send_payment_probes(invoice)?,
+        };
+
+        let fee_msat = match amount_msat {
+            Some(amount_msat) => self
+                .node
+                .estimate_routing_fees_using_amount(invoice, amount_msat)?,
+            None => self.node.estimate_routing_fees(invoice)?,
+        };
+        self.wait_for_probe_outcome(destination, recorded_probes)
+            .await;
+
+        Ok(self.route_fees.store(destination, amount, fee_msat))
+    }
+
+    /// Probes the route to the destination and estimates the routing fee of a keysend payment.
+    pub async fn estimate_keysend_route_fee(
+        &self,
+        destination: PublicKey,
+        amount_msat: u64,
+    ) -> Result<RouteFeeEstimate> {
+        if let Some(estimate) = self.route_fees.cached(destination, amount_msat) {
+            return Ok(estimate);
+        }
+
+        let recorded_probes = self.route_fees.recorded_probes(destination);
+        self.node
+            .send_spontaneous_payment_probes(amount_msat, destination)?;
+
+        let fee_msat = self
             .node
-            .send_spontaneous_payment_probes(amount_msat, destination)
+            .estimate_spontaneous_routing_fees(amount_msat, destination)?;
+        self.wait_for_probe_outcome(destination, recorded_probes)
+            .await;
+
+        Ok(self.route_fees.store(destination, amount_msat, fee_msat))
+    }
+
+    /// Waits until the outcome of another probe to the destination is recorded, so the
+    /// success likelihood includes the probes just sent.
+    async fn wait_for_probe_outcome(&self, destination: PublicKey, recorded_probes: u64) {
+        let deadline = tokio::time::Instant::now() + PROBE_OUTCOME_TIMEOUT;
+        while self.route_fees.recorded_probes(destination) == recorded_probes
+            && tokio::time::Instant::now() < deadline
         {
-            Ok(_) => Ok(self
-                .node
-                .send_spontaneous_payment(amount_msat, destination)?),
-            Err(err) => Err(err.into()),
+            sleep(PAYMENT_STATUS_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Signs the message with the node key, returning a zbase32 signature as LND and CLN do.
+    pub fn sign_message(&self, message: &[u8]) -> Result<String> {
+        Ok(self.node.sign_message(message)?)
+    }
+
+    /// Recovers the node id which signed the message, `None` if the signature is malformed.
+    pub fn recover_signer(&self, message: &[u8], signature: &str) -> Option<PublicKey> {
+        message_signing::recover_pk(message, signature).ok()
+    }
+
+    pub fn verify_signature(&self, message: &[u8], signature: &str, node_id: &PublicKey) -> bool {
+        self.node.verify_signature(message, signature, node_id)
+    }
+
+    pub fn get_payment(&self, payment_hash: &PaymentHash) -> Option<PaymentDetails> {
+        self.node.payment(payment_hash)
+    }
+
+    /// Unix timestamp in seconds of when the payment was created.
+    ///
+    /// Payments without a recorded time, like those created before times were recorded or
+    /// spontaneous payments we received, get the time of their latest update when first seen.
+    pub fn payment_created_at(&self, payment: &PaymentDetails) -> u64 {
+        match self.payment_times.get(&payment.hash) {
+            Some(created_at) => created_at,
+            None => {
+                self.record_payment_time(&payment.hash, payment.latest_update_timestamp);
+                payment.latest_update_timestamp
+            }
+        }
+    }
+
+    /// Records when a payment was created, a payment without a recorded time falls back to
+    /// its latest update, so failures are only logged.
+    fn record_payment_time(&self, payment_hash: &PaymentHash, created_at: u64) {
+        if let Err(err) = self.payment_times.record(payment_hash, created_at) {
+            error!(
+                "Cannot record creation time of payment {}: {}",
+                payment_hash, err
+            );
+        }
+    }
+
+    /// Lists payments matching the filters with their creation time, most recently created
+    /// first. The timestamps filter by creation time.
+    pub fn list_payments(
+        &self,
+        direction: Option<PaymentDirection>,
+        status: Option<PaymentStatus>,
+        from_timestamp: Option<u64>,
+        to_timestamp: Option<u64>,
+    ) -> Vec<(PaymentDetails, u64)> {
+        let mut payments: Vec<(PaymentDetails, u64)> = self
+            .node
+            .list_payments_with_filter(|p| {
+                direction.map_or(true, |d| p.direction == d)
+                    && status.map_or(true, |s| p.status == s)
+            })
+            .into_iter()
+            .map(|p| {
+                let created_at = self.payment_created_at(&p);
+                (p, created_at)
+            })
+            .filter(|(_, created_at)| {
+                from_timestamp.map_or(true, |t| *created_at >= t)
+                    && to_timestamp.map_or(true, |t| *created_at <= t)
+            })
+            .collect();
+        payments.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        payments
+    }
+
+    /// Waits until the payment leaves the pending state or the timeout elapses.
+    pub async fn wait_for_payment(
+        &self,
+        payment_hash: &PaymentHash,
+        timeout: Duration,
+    ) -> Option<PaymentDetails> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let payment = self.get_payment(payment_hash);
+            match &payment {
+                Some(p) if p.status == PaymentStatus::Pending => {}
+                _ => return payment,
+            }
+
+            if tokio::time::Instant::now() >= deadline {
+                return payment;
+            }
+            sleep(PAYMENT_STATUS_POLL_INTERVAL).await;
         }
     }
 
     fn subscribe_events(&self) {
         let node = self.node.clone();
-        let events = self.events.clone();
+        let outbox = self.outbox.clone();
+        let outbox_notify = self.outbox_notify.clone();
+        let event_feed = self.event_feed.clone();
+        let held_payments = self.held_payments.clone();
+        let jit_channels = self.jit_channels.clone();
+        let route_fees = self.route_fees.clone();
         tokio::spawn(async move {
             loop {
                 match node.next_event() {
                     Some(event) => {
-                        let events = events.lock().await;
                         println!("New event: {:?}", event);
 
-                        match event {
-                            Event::PaymentReceived {
-                                payment_hash,
-                                amount_msat,
-                            } => {
-                                events
-                                    .notify(
-                                        LightningNodeEvent::PaymentReceived {
-                                            payment_hash: payment_hash.to_string(),
-                                            amount_msat: amount_msat,
-                                        },
-                                        &payment_hash.to_string(),
-                                    )
-                                    .await;
+                        if let Event::PaymentClaimable {
+                            payment_hash,
+                            claimable_amount_msat,
+                            claim_deadline,
+                            ..
+                        } = &event
+                        {
+                            let held_payment = HeldPayment {
+                                amount_msat: *claimable_amount_msat,
+                                claim_deadline: *claim_deadline,
+                            };
+                            // The event is handed out again until it's marked handled
+                            if let Err(err) = held_payments.insert(*payment_hash, held_payment) {
+                                error!("Cannot store held payment {}: {}", payment_hash, err);
+                                sleep(OUTBOX_MIN_RETRY_DELAY).await;
+                                continue;
                             }
-                            _ => {}
                         }
 
-                        // Todo: blocking events
+                        jit_channels.handle_event(&node, &event);
+
+                        match &event {
+                            Event::ProbeSuccessful { path, .. } => {
+                                if let Some(hop) = path.hops.last() {
+                                    route_fees.record_probe(hop.pubkey, true);
+                                }
+                            }
+                            Event::ProbeFailed { path, .. } => {
+                                if let Some(hop) = path.hops.last() {
+                                    route_fees.record_probe(hop.pubkey, false);
+                                }
+                            }
+                            _ => {}
+                        }
 
-                        node.event_handled();
+                        // The event is only marked handled once it's safely stored in the outbox,
+                        // otherwise LDK hands it to us again
+                        let (message, routing_key) = match node_events::map_event(&event, &node) {
+                            Some(mapped) => mapped,
+                            None => {
+                                node.event_handled();
+                                continue;
+                            }
+                        };
+                        match outbox.push(message.clone(), &routing_key) {
+                            Ok(sequence) => {
+                                node.event_handled();
+                                outbox_notify.notify_one();
+                                event_feed.publish(sequence, message);
+                            }
+                            Err(err) => {
+                                error!("Cannot store event in outbox: {}", err);
+                                sleep(OUTBOX_MIN_RETRY_DELAY).await;
+                            }
+                        }
                     }
                     None => {
                         sleep(Duration::from_millis(100)).await;
@@ -223,28 +869,92 @@ impl NodeProcessor {
         });
     }
 
-    pub async fn trigger_payment_event(&self, payment_hash: Option<String>) {
-        let events = self.events.lock().await;
+    /// Subscribes to node events, replaying buffered events after the cursor first.
+    pub fn subscribe_event_feed(
+        &self,
+        cursor: Option<u64>,
+    ) -> Result<(Vec<FeedEvent>, broadcast::Receiver<FeedEvent>)> {
+        self.event_feed.subscribe(cursor)
+    }
+
+    /// Publishes outbox entries in order, an entry is deleted only after the broker confirmed it.
+    fn publish_outbox(&self) {
+        let source = format!("lightning-node/{}", self.node.node_id());
+        let events = self.events.clone();
+        let outbox = self.outbox.clone();
+        let outbox_notify = self.outbox_notify.clone();
+        tokio::spawn(async move {
+            let mut retry_delay = OUTBOX_MIN_RETRY_DELAY;
+            loop {
+                let pending = match outbox.pending() {
+                    Ok(pending) => pending,
+                    Err(err) => {
+                        error!("Cannot read outbox: {}", err);
+                        sleep(retry_delay).await;
+                        continue;
+                    }
+                };
+
+                if pending.is_empty() {
+                    outbox_notify.notified().await;
+                    continue;
+                }
+
+                for (sequence, entry) in pending {
+                    let envelope =
+                        EventEnvelope::new(entry.id, source.clone(), entry.timestamp, entry.event);
+                    match events.publish(envelope, &entry.routing_key).await {
+                        Ok(_) => {
+                            retry_delay = OUTBOX_MIN_RETRY_DELAY;
+                            if let Err(err) = outbox.remove(sequence) {
+                                error!("Cannot remove published event from outbox: {}", err);
+                            }
+                        }
+                        Err(err) => {
+                            warn!(
+                                "Cannot publish event, retrying in {:?}: {}",
+                                retry_delay, err
+                            );
+                            sleep(retry_delay).await;
+                            retry_delay = (retry_delay * 2).min(OUTBOX_MAX_RETRY_DELAY);
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    pub async fn trigger_payment_event(&self, payment_hash: Option<String>) -> Result<()> {
+        if self.network == Network::Bitcoin {
+            bail!("Fake payment events are not allowed on {}", self.network);
+        }
+
         let fake_hash = if payment_hash.is_none() {
             let mut fake_hash = [0; 32];
             OsRng.fill_bytes(&mut fake_hash);
             PaymentHash(fake_hash)
         } else {
-            let hash =
-                ldk_node::bitcoin::hashes::sha256::Hash::from_str(payment_hash.unwrap().as_str())
-                    .unwrap();
+            let hash = match ldk_node::bitcoin::hashes::sha256::Hash::from_str(
+                payment_hash.unwrap().as_str(),
+            ) {
+                Ok(hash) => hash,
+                Err(_) => bail!(NodeError::InvalidPaymentHash),
+            };
             let mut fake_hash = [0; 32];
             fake_hash.copy_from_slice(hash.as_byte_array().to_vec().as_slice());
             PaymentHash(fake_hash)
         };
-        events
-            .notify(
-                LightningNodeEvent::PaymentReceived {
-                    payment_hash: fake_hash.to_string(),
-                    amount_msat: 350000,
-                },
-                &fake_hash.to_string(),
-            )
-            .await;
+        let event = LightningNodeEvent::PaymentReceived {
+            payment_hash: fake_hash.to_string(),
+            amount_msat: 350000,
+            offer_id: None,
+            custom_records: vec![],
+        };
+        let sequence = self.outbox.push(event.clone(), &fake_hash.to_string())?;
+        self.outbox_notify.notify_one();
+        self.event_feed.publish(sequence, event);
+
+        Ok(())
     }
 }
@@ -0,0 +1,130 @@
+use events::messages::{CustomTlvRecord, LightningNodeEvent};
+use ldk_node::{io::sqlite_store::SqliteStore, Event, Node};
+
+use crate::utils::to_hex;
+
+/// Maps an LDK event to the bus message and the routing key it's published with.
+///
+/// Returns `None` for events the node handles itself and doesn't publish.
+pub fn map_event(event: &Event, node: &Node<SqliteStore>) -> Option<(LightningNodeEvent, String)> {
+    match event {
+        Event::PaymentSuccessful {
+            payment_hash,
+            fee_paid_msat,
+            ..
+        } => Some((
+            LightningNodeEvent::PaymentSuccessful {
+                payment_hash: payment_hash.to_string(),
+                fee_paid_msat: *fee_paid_msat,
+            },
+            payment_hash.to_string(),
+        )),
+        Event::PaymentFailed {
+            payment_hash,
+            reason,
+            ..
+        } => Some((
+            LightningNodeEvent::PaymentFailed {
+                payment_hash: payment_hash.to_string(),
+                reason: reason.map(|r| format!("{:?}", r)),
+            },
+            payment_hash.to_string(),
+        )),
+        Event::PaymentReceived {
+            payment_hash,
+            amount_msat,
+            custom_records,
+            ..
+        } => Some((
+            LightningNodeEvent::PaymentReceived {
+                payment_hash: payment_hash.to_string(),
+                amount_msat: *amount_msat,
+                offer_id: node
+                    .payment(payment_hash)
+                    .and_then(|p| p.offer_id)
+                    .map(|offer_id| to_hex(&offer_id.0)),
+                custom_records: custom_records
+                    .iter()
+                    .map(|(tlv_type, value)| CustomTlvRecord {
+                        tlv_type: *tlv_type,
+                        value: to_hex(value),
+                    })
+                    .collect(),
+            },
+            payment_hash.to_string(),
+        )),
+        Event::PaymentClaimable {
+            payment_hash,
+            claimable_amount_msat,
+            claim_deadline,
+            ..
+        } => Some((
+            LightningNodeEvent::PaymentClaimable {
+                payment_hash: payment_hash.to_string(),
+                claimable_amount_msat: *claimable_amount_msat,
+                claim_deadline: *claim_deadline,
+            },
+            payment_hash.to_string(),
+        )),
+        Event::PaymentForwarded {
+            prev_channel_id,
+            next_channel_id,
+            total_fee_earned_msat,
+            claim_from_onchain_tx,
+            outbound_amount_forwarded_msat,
+            ..
+        } => Some((
+            LightningNodeEvent::PaymentForwarded {
+                prev_channel_id: prev_channel_id.to_string(),
+                next_channel_id: next_channel_id.to_string(),
+                total_fee_earned_msat: *total_fee_earned_msat,
+                claim_from_onchain_tx: *claim_from_onchain_tx,
+                outbound_amount_forwarded_msat: *outbound_amount_forwarded_msat,
+            },
+            next_channel_id.to_string(),
+        )),
+        Event::ChannelPending {
+            channel_id,
+            user_channel_id,
+            former_temporary_channel_id,
+            counterparty_node_id,
+            funding_txo,
+        } => Some((
+            LightningNodeEvent::ChannelPending {
+                channel_id: channel_id.to_string(),
+                user_channel_id: user_channel_id.0.to_string(),
+                former_temporary_channel_id: former_temporary_channel_id.to_string(),
+                counterparty_node_id: counterparty_node_id.to_string(),
+                funding_txo: funding_txo.to_string(),
+            },
+            channel_id.to_string(),
+        )),
+        Event::ChannelReady {
+            channel_id,
+            user_channel_id,
+            counterparty_node_id,
+        } => Some((
+            LightningNodeEvent::ChannelReady {
+                channel_id: channel_id.to_string(),
+                user_channel_id: user_channel_id.0.to_string(),
+                counterparty_node_id: counterparty_node_id.map(|id| id.to_string()),
+            },
+            channel_id.to_string(),
+        )),
+        Event::ChannelClosed {
+            channel_id,
+            user_channel_id,
+            counterparty_node_id,
+            reason,
+        } => Some((
+            LightningNodeEvent::ChannelClosed {
+                channel_id: channel_id.to_string(),
+                user_channel_id: user_channel_id.0.to_string(),
+                counterparty_node_id: counterparty_node_id.map(|id| id.to_string()),
+                reason: reason.as_ref().map(|r| r.to_string()),
+            },
+            channel_id.to_string(),
+        )),
+        Event::HTLCIntercepted { .. } => None,
+    }
+}
@@ -0,0 +1,261 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use events::messages::LightningNodeEvent;
+use log::error;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const OUTBOX_DIR_NAME: &str = "outbox";
+const SEQUENCE_FILE_NAME: &str = "sequence";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub routing_key: String,
+    pub event: LightningNodeEvent,
+    /// Event id sent with every publish attempt, so consumers can drop duplicates. Entries
+    /// stored before ids were assigned get one the first time they're read.
+    #[serde(default)]
+    pub id: String,
+    /// Unix timestamp in milliseconds of when the event was stored
+    #[serde(default = "now_millis")]
+    pub timestamp: u64,
+}
+
+/// Durable queue of bus events kept in the node's data dir until the broker confirms them.
+///
+/// Every entry is one file named by a sequence number, so entries are published in the
+/// order the node emitted them and survive restarts. The last assigned sequence number is
+/// recorded as well, so sequence numbers keep growing after the outbox was drained.
+pub struct Outbox {
+    dir: PathBuf,
+    next_sequence: Mutex<u64>,
+}
+
+impl Outbox {
+    pub fn open(data_dir: &str) -> Result<Self> {
+        let dir = Path::new(data_dir).join(OUTBOX_DIR_NAME);
+        fs::create_dir_all(&dir)?;
+
+        let stored_sequence = match fs::read_to_string(dir.join(SEQUENCE_FILE_NAME)) {
+            Ok(sequence) => sequence.trim().parse().unwrap_or(0),
+            Err(_) => 0,
+        };
+        let last_sequence = Self::sequences(&dir)?
+            .last()
+            .copied()
+            .unwrap_or(0)
+            .max(stored_sequence);
+
+        Ok(Self {
+            dir,
+            next_sequence: Mutex::new(last_sequence + 1),
+        })
+    }
+
+    /// Sequence number the next pushed entry gets.
+    pub fn next_sequence(&self) -> u64 {
+        *self.next_sequence.lock().unwrap()
+    }
+
+    /// Writes the entry and syncs it to disk before returning.
+    pub fn push(&self, event: LightningNodeEvent, routing_key: &str) -> Result<u64> {
+        // Pushes are serialized so the recorded sequence number never goes backwards
+        let mut next_sequence = self.next_sequence.lock().unwrap();
+        let sequence = *next_sequence;
+        self.write_synced(SEQUENCE_FILE_NAME, sequence.to_string().as_bytes())?;
+        *next_sequence += 1;
+
+        let entry = OutboxEntry {
+            routing_key: routing_key.to_string(),
+            event,
+            id: new_event_id(),
+            timestamp: now_millis(),
+        };
+
+        self.write_synced(&entry_file_name(sequence), &serde_json::to_vec(&entry)?)?;
+
+        Ok(sequence)
+    }
+
+    /// Writes to a temporary file first so a crash never leaves a partial file behind.
+    fn write_synced(&self, file_name: &str, content: &[u8]) -> Result<()> {
+        let tmp_path = self.dir.join(format!("{}.tmp", file_name));
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, self.dir.join(file_name))?;
+        File::open(&self.dir)?.sync_all()?;
+
+        Ok(())
+    }
+
+    /// Returns pending entries, oldest first.
+    ///
+    /// Entries which can't be parsed are renamed to `*.corrupt` and skipped, so they don't
+    /// hold back the rest. Entries without an id are rewritten with the id they're given, so
+    /// every publish attempt carries the same one.
+    pub fn pending(&self) -> Result<Vec<(u64, OutboxEntry)>> {
+        let mut entries = vec![];
+        for sequence in Self::sequences(&self.dir)? {
+            let path = self.entry_path(sequence);
+            let content = fs::read(&path)?;
+            match serde_json::from_slice::<OutboxEntry>(&content) {
+                Ok(mut entry) => {
+                    if entry.id.is_empty() {
+                        entry.id = new_event_id();
+                        self.write_synced(
+                            &entry_file_name(sequence),
+                            &serde_json::to_vec(&entry)?,
+                        )?;
+                    }
+                    entries.push((sequence, entry));
+                }
+                Err(err) => {
+                    let corrupt_path = path.with_extension("json.corrupt");
+                    error!(
+                        "Cannot parse outbox entry {}, moving it to {}: {}",
+                        sequence,
+                        corrupt_path.display(),
+                        err
+                    );
+                    fs::rename(&path, &corrupt_path)?;
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    pub fn remove(&self, sequence: u64) -> Result<()> {
+        Ok(fs::remove_file(self.entry_path(sequence))?)
+    }
+
+    fn entry_path(&self, sequence: u64) -> PathBuf {
+        self.dir.join(entry_file_name(sequence))
+    }
+
+    fn sequences(dir: &Path) -> Result<Vec<u64>> {
+        let mut sequences: Vec<u64> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                match path.extension() {
+                    Some(ext) if ext == "json" => path.file_stem()?.to_str()?.parse().ok(),
+                    _ => None,
+                }
+            })
+            .collect();
+        sequences.sort();
+
+        Ok(sequences)
+    }
+}
+
+fn entry_file_name(sequence: u64) -> String {
+    format!("{:020}.json", sequence)
+}
+
+fn new_event_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use events::messages::LightningNodeEvent;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::Outbox;
+
+    fn event(payment_hash: &str) -> LightningNodeEvent {
+        LightningNodeEvent::PaymentFailed {
+            payment_hash: payment_hash.to_string(),
+            reason: None,
+        }
+    }
+
+    fn data_dir() -> String {
+        let data_dir = env::temp_dir().join(format!("outbox-{}", Uuid::new_v4()));
+        data_dir.to_str().unwrap().to_string()
+    }
+
+    fn pending_events(outbox: &Outbox) -> Vec<(u64, LightningNodeEvent)> {
+        outbox
+            .pending()
+            .unwrap()
+            .into_iter()
+            .map(|(sequence, entry)| (sequence, entry.event))
+            .collect()
+    }
+
+    #[test]
+    fn test_pending_in_order() {
+        let data_dir = data_dir();
+        let outbox = Outbox::open(&data_dir).unwrap();
+
+        for payment_hash in ["a", "b", "c"] {
+            outbox.push(event(payment_hash), "key").unwrap();
+        }
+        outbox.remove(2).unwrap();
+
+        assert_eq!(
+            pending_events(&outbox),
+            vec![(1, event("a")), (3, event("c"))]
+        );
+    }
+
+    #[test]
+    fn test_sequence_survives_reopen() {
+        let data_dir = data_dir();
+        let outbox = Outbox::open(&data_dir).unwrap();
+        outbox.push(event("a"), "key").unwrap();
+        outbox.remove(1).unwrap();
+
+        // The drained outbox keeps counting
+        let outbox = Outbox::open(&data_dir).unwrap();
+        assert_eq!(outbox.next_sequence(), 2);
+        assert_eq!(outbox.push(event("b"), "key").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_quarantines_corrupt_entry() {
+        let data_dir = data_dir();
+        let outbox = Outbox::open(&data_dir).unwrap();
+        outbox.push(event("a"), "key").unwrap();
+        outbox.push(event("b"), "key").unwrap();
+        fs::write(outbox.entry_path(1), b"{").unwrap();
+
+        assert_eq!(pending_events(&outbox), vec![(2, event("b"))]);
+        assert!(outbox.entry_path(1).with_extension("json.corrupt").exists());
+        assert_eq!(pending_events(&outbox), vec![(2, event("b"))]);
+    }
+
+    #[test]
+    fn test_legacy_entry_keeps_assigned_id() {
+        let data_dir = data_dir();
+        let outbox = Outbox::open(&data_dir).unwrap();
+        outbox.push(event("a"), "key").unwrap();
+        let legacy_entry = json!({ "routing_key": "key", "event": event("a") });
+        fs::write(outbox.entry_path(1), legacy_entry.to_string()).unwrap();
+
+        let (_, entry) = outbox.pending().unwrap().remove(0);
+        assert!(!entry.id.is_empty());
+        assert_eq!(outbox.pending().unwrap()[0].1.id, entry.id);
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Result;
+use ldk_node::lightning::ln::PaymentHash;
+use log::warn;
+
+const PAYMENT_TIMES_DIR_NAME: &str = "payment_times";
+
+/// Creation times of payments in unix seconds, LDK only keeps the time of the latest update.
+///
+/// Every time is a file named by the payment hash in the node's data dir, all of them are
+/// loaded when the node starts.
+pub struct PaymentTimes {
+    dir: PathBuf,
+    times: Mutex<HashMap<String, u64>>,
+}
+
+impl PaymentTimes {
+    pub fn open(data_dir: &str) -> Result<Self> {
+        let dir = Path::new(data_dir).join(PAYMENT_TIMES_DIR_NAME);
+        fs::create_dir_all(&dir)?;
+
+        let mut times = HashMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let payment_hash = match path.file_name().and_then(|name| name.to_str()) {
+                Some(payment_hash) => payment_hash.to_string(),
+                None => continue,
+            };
+            match fs::read_to_string(&path)?.trim().parse() {
+                Ok(created_at) => {
+                    times.insert(payment_hash, created_at);
+                }
+                Err(_) => warn!("Ignoring unreadable payment time {}", path.display()),
+            }
+        }
+
+        Ok(Self {
+            dir,
+            times: Mutex::new(times),
+        })
+    }
+
+    pub fn get(&self, payment_hash: &PaymentHash) -> Option<u64> {
+        self.times
+            .lock()
+            .unwrap()
+            .get(&payment_hash.to_string())
+            .copied()
+    }
+
+    /// Records when the payment was created, a payment keeps the first time recorded for it.
+    pub fn record(&self, payment_hash: &PaymentHash, created_at: u64) -> Result<()> {
+        let payment_hash = payment_hash.to_string();
+        let mut times = self.times.lock().unwrap();
+        if times.contains_key(&payment_hash) {
+            return Ok(());
+        }
+
+        fs::write(self.dir.join(&payment_hash), created_at.to_string())?;
+        times.insert(payment_hash, created_at);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use ldk_node::lightning::ln::PaymentHash;
+    use uuid::Uuid;
+
+    use super::PaymentTimes;
+
+    #[test]
+    fn test_keeps_first_time() {
+        let data_dir = env::temp_dir().join(format!("payment-times-{}", Uuid::new_v4()));
+        let data_dir = data_dir.to_str().unwrap();
+        let payment_times = PaymentTimes::open(data_dir).unwrap();
+
+        payment_times.record(&PaymentHash([1; 32]), 100).unwrap();
+        payment_times.record(&PaymentHash([1; 32]), 200).unwrap();
+        assert_eq!(payment_times.get(&PaymentHash([1; 32])), Some(100));
+        assert_eq!(payment_times.get(&PaymentHash([2; 32])), None);
+
+        // Times survive a restart
+        let payment_times = PaymentTimes::open(data_dir).unwrap();
+        assert_eq!(payment_times.get(&PaymentHash([1; 32])), Some(100));
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use ldk_node::bitcoin::secp256k1::PublicKey;
+
+/// How long an estimate is reused for the same destination and amount.
+const ROUTE_FEE_CACHE_TTL: Duration = Duration::from_secs(600);
+/// Number of recent probe outcomes per destination the success likelihood is computed from.
+const PROBE_HISTORY_SIZE: usize = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RouteFeeEstimate {
+    pub fee_msat: u64,
+    /// Share of recent probes to the destination which arrived, between 0 and 1. `None` until
+    /// the outcome of a probe to the destination is known.
+    pub success_likelihood: Option<f64>,
+    pub cached: bool,
+}
+
+struct CachedEstimate {
+    fee_msat: u64,
+    created_at: Instant,
+}
+
+#[derive(Default)]
+struct ProbeHistory {
+    outcomes: VecDeque<bool>,
+    /// Outcomes recorded since the node started, also those no longer in `outcomes`
+    recorded: u64,
+}
+
+/// Caches routing fee estimates and remembers probe outcomes per destination.
+pub struct RouteFees {
+    estimates: Mutex<HashMap<(PublicKey, u64), CachedEstimate>>,
+    probe_history: Mutex<HashMap<PublicKey, ProbeHistory>>,
+}
+
+impl RouteFees {
+    pub fn new() -> Self {
+        Self {
+            estimates: Mutex::new(HashMap::new()),
+            probe_history: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn cached(&self, destination: PublicKey, amount_msat: u64) -> Option<RouteFeeEstimate> {
+        let mut estimates = self.estimates.lock().unwrap();
+        let key = (destination, amount_msat);
+
+        match estimates.get(&key) {
+            Some(cached) if cached.created_at.elapsed() < ROUTE_FEE_CACHE_TTL => {
+                Some(RouteFeeEstimate {
+                    fee_msat: cached.fee_msat,
+                    success_likelihood: self.success_likelihood(destination),
+                    cached: true,
+                })
+            }
+            Some(_) => {
+                estimates.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn store(
+        &self,
+        destination: PublicKey,
+        amount_msat: u64,
+        fee_msat: u64,
+    ) -> RouteFeeEstimate {
+        self.estimates.lock().unwrap().insert(
+            (destination, amount_msat),
+            CachedEstimate {
+                fee_msat,
+                created_at: Instant::now(),
+            },
+        );
+
+        RouteFeeEstimate {
+            fee_msat,
+            success_likelihood: self.success_likelihood(destination),
+            cached: false,
+        }
+    }
+
+    /// Records whether a probe to the destination arrived, from a `ProbeSuccessful` or
+    /// `ProbeFailed` event.
+    pub fn record_probe(&self, destination: PublicKey, arrived: bool) {
+        let mut probe_history = self.probe_history.lock().unwrap();
+        let history = probe_history.entry(destination).or_default();
+        if history.outcomes.len() == PROBE_HISTORY_SIZE {
+            history.outcomes.pop_front();
+        }
+        history.outcomes.push_back(arrived);
+        history.recorded += 1;
+    }
+
+    /// Number of probe outcomes recorded for the destination, to wait for new ones.
+    pub fn recorded_probes(&self, destination: PublicKey) -> u64 {
+        self.probe_history
+            .lock()
+            .unwrap()
+            .get(&destination)
+            .map_or(0, |history| history.recorded)
+    }
+
+    pub fn success_likelihood(&self, destination: PublicKey) -> Option<f64> {
+        let probe_history = self.probe_history.lock().unwrap();
+        match probe_history.get(&destination) {
+            Some(history) if !history.outcomes.is_empty() => Some(
+                history.outcomes.iter().filter(|arrived| **arrived).count() as f64
+                    / history.outcomes.len() as f64,
+            ),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use ldk_node::bitcoin::secp256k1::PublicKey;
+
+    use super::{RouteFees, PROBE_HISTORY_SIZE};
+
+    fn destination() -> PublicKey {
+        PublicKey::from_str("02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619")
+            .unwrap()
+    }
+
+    #[test]
+    fn test_success_likelihood() {
+        let route_fees = RouteFees::new();
+        assert_eq!(route_fees.success_likelihood(destination()), None);
+        assert_eq!(
+            route_fees
+                .store(destination(), 1_000, 10)
+                .success_likelihood,
+            None
+        );
+
+        route_fees.record_probe(destination(), true);
+        route_fees.record_probe(destination(), false);
+        assert_eq!(route_fees.success_likelihood(destination()), Some(0.5));
+        assert_eq!(
+            route_fees
+                .cached(destination(), 1_000)
+                .unwrap()
+                .success_likelihood,
+            Some(0.5)
+        );
+
+        // Only recent outcomes count
+        for _ in 0..PROBE_HISTORY_SIZE {
+            route_fees.record_probe(destination(), true);
+        }
+        assert_eq!(route_fees.success_likelihood(destination()), Some(1.0));
+        assert_eq!(
+            route_fees.recorded_probes(destination()),
+            PROBE_HISTORY_SIZE as u64 + 2
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use ldk_node::NodeError;
+use tonic::{Code, Status};
+use tonic_types::{ErrorDetails, StatusExt};
+
+use super::node_api::ErrorReason;
+
+const ERROR_DOMAIN: &str = "walletka.lightning-node";
+
+/// Error returned by the gRPC handlers.
+///
+/// Converts into a `Status` carrying an `ErrorInfo` detail, whose reason is the name of an
+/// `ErrorReason` value, so clients can act on it without parsing messages.
+#[derive(Debug)]
+pub struct ApiError {
+    code: Code,
+    reason: ErrorReason,
+    message: String,
+    metadata: HashMap<String, String>,
+}
+
+impl ApiError {
+    pub fn new(code: Code, reason: ErrorReason, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            reason,
+            message: message.into(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        Self::new(Code::InvalidArgument, ErrorReason::InvalidArgument, message)
+    }
+
+    pub fn invalid_amount() -> Self {
+        Self::new(
+            Code::InvalidArgument,
+            ErrorReason::InvalidAmount,
+            "Invalid amount",
+        )
+    }
+
+    pub fn invalid_invoice() -> Self {
+        Self::new(
+            Code::InvalidArgument,
+            ErrorReason::InvalidInvoice,
+            "Invalid BOLT11 invoice",
+        )
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(Code::NotFound, ErrorReason::NotFound, message)
+    }
+
+    pub fn failed_precondition(message: impl Into<String>) -> Self {
+        Self::new(
+            Code::FailedPrecondition,
+            ErrorReason::FailedPrecondition,
+            message,
+        )
+    }
+
+    /// Like `From<anyhow::Error>`, but errors other than `NodeError`s are failed preconditions.
+    pub fn precondition(err: anyhow::Error) -> Self {
+        match err.downcast::<NodeError>() {
+            Ok(err) => err.into(),
+            Err(err) => Self::failed_precondition(err.to_string()),
+        }
+    }
+
+    fn with_metadata(mut self, key: &str, value: String) -> Self {
+        self.metadata.insert(key.to_string(), value);
+        self
+    }
+}
+
+impl From<NodeError> for ApiError {
+    fn from(err: NodeError) -> Self {
+        let (code, reason) = match err {
+            NodeError::InvalidAddress
+            | NodeError::InvalidSocketAddress
+            | NodeError::InvalidPublicKey
+            | NodeError::InvalidSecretKey
+            | NodeError::InvalidPaymentHash
+            | NodeError::InvalidPaymentPreimage
+            | NodeError::InvalidPaymentSecret
+            | NodeError::InvalidChannelId
+            | NodeError::InvalidNetwork => (Code::InvalidArgument, ErrorReason::InvalidArgument),
+            NodeError::InvalidAmount => (Code::InvalidArgument, ErrorReason::InvalidAmount),
+            NodeError::InvalidInvoice => (Code::InvalidArgument, ErrorReason::InvalidInvoice),
+            NodeError::NotRunning => (Code::Unavailable, ErrorReason::NodeNotRunning),
+            NodeError::ConnectionFailed => (Code::Unavailable, ErrorReason::ConnectionFailed),
+            NodeError::TxSyncFailed
+            | NodeError::GossipUpdateFailed
+            | NodeError::FeerateEstimationUpdateFailed => {
+                (Code::Unavailable, ErrorReason::SyncFailed)
+            }
+            NodeError::InsufficientFunds => {
+                (Code::FailedPrecondition, ErrorReason::InsufficientFunds)
+            }
+            NodeError::DuplicatePayment => (Code::AlreadyExists, ErrorReason::DuplicatePayment),
+            NodeError::ProbeSendingFailed => (Code::NotFound, ErrorReason::RouteNotFound),
+            NodeError::PaymentSendingFailed => (Code::Aborted, ErrorReason::PaymentFailed),
+            NodeError::ChannelCreationFailed
+            | NodeError::ChannelClosingFailed
+            | NodeError::ChannelConfigUpdateFailed => (
+                Code::FailedPrecondition,
+                ErrorReason::ChannelOperationFailed,
+            ),
+            _ => (Code::Internal, ErrorReason::Internal),
+        };
+
+        Self::new(code, reason, err.to_string()).with_metadata("node_error", format!("{:?}", err))
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast::<NodeError>() {
+            Ok(err) => err.into(),
+            Err(err) => Self::new(Code::Internal, ErrorReason::Internal, err.to_string()),
+        }
+    }
+}
+
+impl From<ApiError> for Status {
+    fn from(err: ApiError) -> Self {
+        Status::with_error_details(
+            err.code,
+            err.message,
+            ErrorDetails::with_error_info(err.reason.as_str_name(), ERROR_DOMAIN, err.metadata),
+        )
+    }
+}
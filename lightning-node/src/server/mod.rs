@@ -1,36 +1,280 @@
-use std::{str::FromStr, sync::Arc};
+use std::{pin::Pin, str::FromStr, sync::Arc, time::Duration};
+
+use events::messages::LightningNodeEvent;
 
 use ldk_node::{
-    bitcoin::secp256k1::PublicKey, lightning::ln::msgs::SocketAddress,
+    bdk::KeychainKind,
+    bitcoin::{
+        hashes::{sha256, Hash},
+        secp256k1::PublicKey,
+        Address, Network,
+    },
+    lightning::{
+        chain::chaininterface::ConfirmationTarget,
+        ln::{msgs::SocketAddress, ChannelId, PaymentHash, PaymentPreimage},
+        offers::{offer::Offer, refund::Refund},
+        util::{config::MaxDustHTLCExposure, ser::Writeable},
+    },
     lightning_invoice::Bolt11Invoice,
+    PaymentDetails,
 };
 use node_api::*;
-use tonic::{Request, Response, Status};
+use tokio::sync::{broadcast::error::RecvError, mpsc};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tonic::{Code, Request, Response, Status};
+
+use grpc_auth::{authorize, Scope};
+
+use crate::{
+    processor::{ChannelPolicy, JitChannelLimitReached, NodeProcessor},
+    utils::to_hex,
+};
+
+use self::error::ApiError;
 
-use crate::processor::NodeProcessor;
+mod error;
 
 pub mod node_api {
     tonic::include_proto!("node_api_service");
 }
 
+/// How long payment RPCs wait for the payment to reach a final status, they answer with a
+/// pending status afterwards.
+const PAYMENT_TIMEOUT: Duration = Duration::from_secs(60);
+const KEYSEND_PREIMAGE_TLV_TYPE: u64 = 5482373484;
+
+fn parse_public_key(public_key: &str) -> Result<PublicKey, Status> {
+    match PublicKey::from_str(public_key) {
+        Ok(public_key) => Ok(public_key),
+        Err(_) => Err(ApiError::invalid_argument("Invalid node id").into()),
+    }
+}
+
+fn parse_socket_address(address: &str) -> Result<SocketAddress, Status> {
+    match SocketAddress::from_str(address) {
+        Ok(address) => Ok(address),
+        Err(_) => Err(ApiError::invalid_argument("Invalid address").into()),
+    }
+}
+
+fn parse_hex32(value: &str) -> Option<[u8; 32]> {
+    sha256::Hash::from_str(value)
+        .ok()
+        .map(|hash| hash.to_byte_array())
+}
+
+fn parse_address(address: &str, network: Network) -> Result<Address, Status> {
+    match Address::from_str(address).map(|a| a.require_network(network)) {
+        Ok(Ok(address)) => Ok(address),
+        _ => Err(ApiError::invalid_argument(format!("Invalid {} address", network)).into()),
+    }
+}
+
+fn parse_channel_id(channel_id: &str) -> Result<ChannelId, Status> {
+    match parse_hex32(channel_id) {
+        Some(channel_id) => Ok(ChannelId(channel_id)),
+        None => Err(ApiError::invalid_argument("Invalid channel id").into()),
+    }
+}
+
+fn channel_policy(policy: Option<ChannelPolicyMessage>) -> Result<ChannelPolicy, Status> {
+    let policy = match policy {
+        Some(policy) => policy,
+        None => return Ok(ChannelPolicy::default()),
+    };
+
+    let cltv_expiry_delta = match policy.cltv_expiry_delta {
+        Some(delta) => match u16::try_from(delta) {
+            Ok(delta) => Some(delta),
+            Err(_) => return Err(ApiError::invalid_argument("Invalid CLTV expiry delta").into()),
+        },
+        None => None,
+    };
+
+    let max_dust_htlc_exposure = match (
+        policy.max_dust_htlc_exposure_msat,
+        policy.max_dust_htlc_exposure_fee_rate_multiplier,
+    ) {
+        (Some(_), Some(_)) => {
+            return Err(ApiError::invalid_argument(
+                "Set either a fixed max dust HTLC exposure or a fee rate multiplier",
+            )
+            .into())
+        }
+        (Some(limit_msat), None) => Some(MaxDustHTLCExposure::FixedLimitMsat(limit_msat)),
+        (None, Some(multiplier)) => Some(MaxDustHTLCExposure::FeeRateMultiplier(multiplier)),
+        (None, None) => None,
+    };
+
+    Ok(ChannelPolicy {
+        forwarding_fee_base_msat: policy.forwarding_fee_base_msat,
+        forwarding_fee_proportional_millionths: policy.forwarding_fee_proportional_millionths,
+        cltv_expiry_delta,
+        max_dust_htlc_exposure,
+    })
+}
+
+fn channel_policy_message(policy: &ChannelPolicy) -> ChannelPolicyMessage {
+    let (max_dust_htlc_exposure_msat, max_dust_htlc_exposure_fee_rate_multiplier) =
+        match policy.max_dust_htlc_exposure {
+            Some(MaxDustHTLCExposure::FixedLimitMsat(limit_msat)) => (Some(limit_msat), None),
+            Some(MaxDustHTLCExposure::FeeRateMultiplier(multiplier)) => (None, Some(multiplier)),
+            None => (None, None),
+        };
+
+    ChannelPolicyMessage {
+        forwarding_fee_base_msat: policy.forwarding_fee_base_msat,
+        forwarding_fee_proportional_millionths: policy.forwarding_fee_proportional_millionths,
+        cltv_expiry_delta: policy.cltv_expiry_delta.map(u32::from),
+        max_dust_htlc_exposure_msat,
+        max_dust_htlc_exposure_fee_rate_multiplier,
+    }
+}
+
+/// Keysend uses type 5482373484 for the preimage, custom records must be at least 2^16.
+fn custom_tlvs(records: Vec<CustomTlvRecord>) -> Result<Vec<(u64, Vec<u8>)>, Status> {
+    let mut tlvs: Vec<(u64, Vec<u8>)> = vec![];
+    for record in records {
+        if record.tlv_type < 1 << 16 || record.tlv_type == KEYSEND_PREIMAGE_TLV_TYPE {
+            return Err(ApiError::invalid_argument(format!(
+                "Invalid custom TLV type {}",
+                record.tlv_type
+            ))
+            .into());
+        }
+        if tlvs
+            .iter()
+            .any(|(tlv_type, _)| *tlv_type == record.tlv_type)
+        {
+            return Err(ApiError::invalid_argument(format!(
+                "Duplicate custom TLV type {}",
+                record.tlv_type
+            ))
+            .into());
+        }
+        tlvs.push((record.tlv_type, record.value));
+    }
+    // Onion TLV streams are ordered by type
+    tlvs.sort_by_key(|(tlv_type, _)| *tlv_type);
+
+    Ok(tlvs)
+}
+
+fn parse_payment_hash(payment_hash: &str) -> Result<PaymentHash, Status> {
+    match parse_hex32(payment_hash) {
+        Some(hash) => Ok(PaymentHash(hash)),
+        None => Err(ApiError::invalid_argument("Invalid payment hash").into()),
+    }
+}
+
+fn parse_preimage(preimage: &str) -> Result<PaymentPreimage, Status> {
+    match parse_hex32(preimage) {
+        Some(preimage) => Ok(PaymentPreimage(preimage)),
+        None => Err(ApiError::invalid_argument("Invalid preimage").into()),
+    }
+}
+
+/// Converts a fee rate in sats per 1000 weight units to sats per vbyte.
+fn sat_per_vbyte(sat_per_kw: u32) -> f64 {
+    sat_per_kw as f64 / 250.0
+}
+
+fn node_event_message(cursor: u64, event: &LightningNodeEvent) -> Result<NodeEvent, Status> {
+    match serde_json::to_string(event) {
+        Ok(payload) => Ok(NodeEvent {
+            cursor,
+            event_type: event.event_type().to_string(),
+            payload,
+        }),
+        Err(err) => Err(ApiError::from(anyhow::Error::from(err)).into()),
+    }
+}
+
+fn payment_direction(direction: &ldk_node::PaymentDirection) -> PaymentDirection {
+    match direction {
+        ldk_node::PaymentDirection::Inbound => PaymentDirection::Inbound,
+        ldk_node::PaymentDirection::Outbound => PaymentDirection::Outbound,
+    }
+}
+
+fn payment_status(status: &ldk_node::PaymentStatus) -> PaymentStatus {
+    match status {
+        ldk_node::PaymentStatus::Pending => PaymentStatus::Pending,
+        ldk_node::PaymentStatus::Succeeded => PaymentStatus::Succeeded,
+        ldk_node::PaymentStatus::Failed => PaymentStatus::Failed,
+    }
+}
+
+fn payment_details_message(p: &PaymentDetails, created_at: u64) -> PaymentDetailsMessage {
+    PaymentDetailsMessage {
+        payment_hash: p.hash.to_string(),
+        preimage: p.preimage.map(|p| p.to_string()).unwrap_or_default(),
+        amount_msat: p.amount_msat.unwrap_or_default(),
+        fee_paid_msat: p.fee_paid_msat.unwrap_or_default(),
+        direction: payment_direction(&p.direction).into(),
+        status: payment_status(&p.status).into(),
+        updated_at: p.latest_update_timestamp,
+        created_at,
+    }
+}
+
 pub struct LightningNodeGrpcServer {
     pub node: Arc<NodeProcessor>,
 }
 
 #[tonic::async_trait]
 impl node_server::Node for LightningNodeGrpcServer {
-    async fn get_info(&self, _: Request<()>) -> Result<Response<GetInfoResponse>, Status> {
+    type SubscribeEventsStream = Pin<Box<dyn Stream<Item = Result<NodeEvent, Status>> + Send>>;
+
+    async fn get_info(&self, request: Request<()>) -> Result<Response<GetInfoResponse>, Status> {
+        authorize(&request, Scope::ReadOnly)?;
+
+        let sync_status = self.node.sync_status();
+        let channels = self.node.get_channels();
+        let (onchain_spendable_balance_sats, onchain_total_balance_sats) =
+            self.node.get_onchain_balance().map_err(ApiError::from)?;
+
         Ok(Response::new(GetInfoResponse {
             node_id: self.node.get_id().to_string(),
-            running: true,
-            onchain_address: self.node.new_onchain_address().unwrap().to_string(),
+            running: sync_status.is_running,
+            chain_source: sync_status.chain_source,
+            gossip_source: sync_status.gossip_source,
+            best_block_height: sync_status.best_block_height,
+            latest_lightning_wallet_sync_timestamp: sync_status
+                .latest_lightning_wallet_sync_timestamp
+                .unwrap_or_default(),
+            latest_onchain_wallet_sync_timestamp: sync_status
+                .latest_onchain_wallet_sync_timestamp
+                .unwrap_or_default(),
+            latest_rgs_snapshot_timestamp: sync_status
+                .latest_rgs_snapshot_timestamp
+                .unwrap_or_default(),
+            network_graph_nodes: sync_status.network_graph_nodes,
+            network_graph_channels: sync_status.network_graph_channels,
+            alias: self.node.alias().unwrap_or_default(),
+            network: self.node.network().to_string(),
+            num_peers: self
+                .node
+                .get_peers()
+                .iter()
+                .filter(|p| p.is_connected)
+                .count() as u32,
+            num_channels: channels.len() as u32,
+            num_usable_channels: channels.iter().filter(|c| c.is_usable).count() as u32,
+            num_pending_channels: channels.iter().filter(|c| !c.is_channel_ready).count() as u32,
+            onchain_spendable_balance_sats,
+            onchain_total_balance_sats,
+            lightning_balance_msat: channels.iter().map(|c| c.outbound_capacity_msat).sum(),
+            lightning_inbound_capacity_msat: channels.iter().map(|c| c.inbound_capacity_msat).sum(),
         }))
     }
 
     async fn get_channels(
         &self,
-        _: Request<GetChannelsRequest>,
+        request: Request<GetChannelsRequest>,
     ) -> Result<Response<GetChannelsResponse>, Status> {
+        authorize(&request, Scope::ReadOnly)?;
+
         let channels = self.node.get_channels();
         let channels: Vec<ChannelDetailsMessage> = channels
             .iter()
@@ -52,6 +296,10 @@ impl node_server::Node for LightningNodeGrpcServer {
                 is_public: c.is_public,
                 inbound_htlc_minimum_msat: c.inbound_htlc_minimum_msat,
                 inbound_htlc_maximum_msat: c.inbound_htlc_maximum_msat.unwrap_or_default(),
+                policy: c
+                    .config
+                    .as_ref()
+                    .map(|config| channel_policy_message(&ChannelPolicy::from_config(config))),
             })
             .collect();
 
@@ -62,23 +310,37 @@ impl node_server::Node for LightningNodeGrpcServer {
         &self,
         request: Request<OpenChannelRequest>,
     ) -> Result<Response<OpenChannelResponse>, Status> {
+        authorize(&request, Scope::Admin)?;
+
         let r: OpenChannelRequest = request.into_inner();
 
+        let node_id = parse_public_key(&r.node_id)?;
+        let policy = channel_policy(r.policy)?;
         let address = if r.address.len() > 1 {
-            Some(SocketAddress::from_str(&r.address).unwrap())
+            Some(parse_socket_address(&r.address)?)
         } else {
             None
         };
 
+        if address.is_none() && !self.node.get_peers().iter().any(|p| p.node_id == node_id) {
+            return Err(ApiError::new(
+                Code::FailedPrecondition,
+                ErrorReason::UnknownPeer,
+                "Peer is not known, provide address",
+            )
+            .into());
+        }
+
         match self.node.open_channel(
-            PublicKey::from_str(&r.node_id).unwrap(),
+            node_id,
             address,
             r.channel_amount_sats,
             Some(r.push_to_counterparty_msat),
             r.public,
+            &policy,
         ) {
             Ok(_) => Ok(Response::new(OpenChannelResponse {})),
-            Err(err) => Err(Status::new(tonic::Code::Unknown, err.to_string())),
+            Err(err) => Err(ApiError::from(err).into()),
         }
     }
 
@@ -86,19 +348,45 @@ impl node_server::Node for LightningNodeGrpcServer {
         &self,
         request: Request<CloseChannelRequest>,
     ) -> Result<Response<CloseChannelResponse>, Status> {
+        authorize(&request, Scope::Admin)?;
+
         let r: CloseChannelRequest = request.into_inner();
+        let channel_id = parse_channel_id(&r.channel_id)?;
+        let close_to = if r.close_to_address.is_empty() {
+            None
+        } else {
+            Some(parse_address(&r.close_to_address, self.node.network())?)
+        };
 
-        match self
+        if r.force && close_to.is_some() {
+            return Err(ApiError::invalid_argument(
+                "Close-to address is only supported for cooperative closes",
+            )
+            .into());
+        }
+
+        let channel = match self
             .node
             .get_channels()
-            .iter()
-            .find(|c| c.channel_id.to_string() == r.channel_id)
+            .into_iter()
+            .find(|c| c.channel_id == channel_id)
+        {
+            Some(channel) => channel,
+            None => return Err(ApiError::not_found("Channel not found").into()),
+        };
+
+        match self
+            .node
+            .close_channel(channel.channel_id, r.force, close_to)
+            .await
         {
-            Some(channel) => match self.node.close_channel(channel.channel_id).await {
-                Ok(_) => Ok(Response::new(CloseChannelResponse {})),
-                Err(err) => Err(Status::new(tonic::Code::Unknown, err.to_string())),
-            },
-            None => todo!(),
+            Ok(_) => Ok(Response::new(CloseChannelResponse {
+                funding_txo: channel
+                    .funding_txo
+                    .map(|txo| txo.to_string())
+                    .unwrap_or_default(),
+            })),
+            Err(err) => Err(ApiError::from(err).into()),
         }
     }
 
@@ -106,16 +394,18 @@ impl node_server::Node for LightningNodeGrpcServer {
         &self,
         request: Request<CreateBolt11InvoiceRequest>,
     ) -> Result<Response<CreateBolt11InvoiceResponse>, Status> {
+        authorize(&request, Scope::Invoice)?;
+
         let r = request.into_inner();
-        let bolt11_invoice = if r.amount_msat > 0 {
-            self.node
-                .create_bolt11_invoice(Some(r.amount_msat), &r.description, r.expiry_secs)
-                .unwrap()
+        let amount_msat = if r.amount_msat > 0 {
+            Some(r.amount_msat)
         } else {
-            self.node
-                .create_bolt11_invoice(None, &r.description, r.expiry_secs)
-                .unwrap()
+            None
         };
+        let bolt11_invoice = self
+            .node
+            .create_bolt11_invoice(amount_msat, &r.description, r.expiry_secs)
+            .map_err(ApiError::from)?;
 
         Ok(Response::new(CreateBolt11InvoiceResponse {
             invoice: bolt11_invoice.to_string(),
@@ -126,26 +416,48 @@ impl node_server::Node for LightningNodeGrpcServer {
         &self,
         request: Request<PayInvoiceRequest>,
     ) -> Result<Response<PayInvoiceResponse>, Status> {
+        authorize(&request, Scope::Payments)?;
+
         let r = request.into_inner();
-        let bolt11_invoice = Bolt11Invoice::from_str(&r.bolt11_invoice).unwrap();
+        let bolt11_invoice =
+            Bolt11Invoice::from_str(&r.bolt11_invoice).map_err(|_| ApiError::invalid_invoice())?;
 
-        if bolt11_invoice.amount_milli_satoshis().is_some() {
-            self.node.pay_invoice(&bolt11_invoice, None).unwrap()
+        let payment_hash = if bolt11_invoice.amount_milli_satoshis().is_some() {
+            self.node
+                .pay_invoice(&bolt11_invoice, None)
+                .map_err(ApiError::from)?
         } else if r.amount_msat > 0 {
             self.node
                 .pay_invoice(&bolt11_invoice, Some(r.amount_msat))
-                .unwrap()
+                .map_err(ApiError::from)?
         } else {
-            return Err(Status::new(tonic::Code::Aborted, "Invalid amount"));
+            return Err(ApiError::invalid_amount().into());
         };
 
-        Ok(Response::new(PayInvoiceResponse {}))
+        let payment = self
+            .node
+            .wait_for_payment(&payment_hash, PAYMENT_TIMEOUT)
+            .await;
+
+        Ok(Response::new(PayInvoiceResponse {
+            payment_hash: payment_hash.to_string(),
+            status: payment
+                .as_ref()
+                .map(|p| payment_status(&p.status))
+                .unwrap_or(PaymentStatus::Pending)
+                .into(),
+            created_at: payment
+                .map(|p| self.node.payment_created_at(&p))
+                .unwrap_or_default(),
+        }))
     }
 
     async fn trigger_payment_event(
         &self,
         request: Request<TriggerPaymentEventRequest>,
     ) -> Result<Response<()>, Status> {
+        authorize(&request, Scope::Admin)?;
+
         let r = request.into_inner();
         let payment_hash = if r.payment_hash.len() > 1 {
             Some(r.payment_hash)
@@ -153,23 +465,705 @@ impl node_server::Node for LightningNodeGrpcServer {
             None
         };
 
-        self.node.trigger_payment_event(payment_hash).await;
-        Ok(Response::new(()))
+        match self.node.trigger_payment_event(payment_hash).await {
+            Ok(_) => Ok(Response::new(())),
+            Err(err) => Err(ApiError::precondition(err).into()),
+        }
     }
 
     async fn send_keysend_payment(
         &self,
         request: Request<SendKeysendPaymentRequest>,
     ) -> Result<Response<SendKeysendPaymentResponse>, Status> {
+        authorize(&request, Scope::Payments)?;
+
         let r = request.into_inner();
+        let destination = parse_public_key(&r.destination)?;
+        let custom_tlvs = custom_tlvs(r.custom_records)?;
+
         match self
             .node
-            .send_keysend_payment(PublicKey::from_str(&r.destination).unwrap(), r.amount)
+            .send_keysend_payment(destination, r.amount, custom_tlvs)
         {
-            Ok(payment_hash) => Ok(Response::new(SendKeysendPaymentResponse {
+            Ok(payment_hash) => {
+                let payment = self
+                    .node
+                    .wait_for_payment(&payment_hash, PAYMENT_TIMEOUT)
+                    .await;
+
+                Ok(Response::new(SendKeysendPaymentResponse {
+                    payment_hash: payment_hash.to_string(),
+                    status: payment
+                        .as_ref()
+                        .map(|p| payment_status(&p.status))
+                        .unwrap_or(PaymentStatus::Pending)
+                        .into(),
+                    created_at: payment
+                        .map(|p| self.node.payment_created_at(&p))
+                        .unwrap_or_default(),
+                }))
+            }
+            Err(err) => Err(ApiError::from(err).into()),
+        }
+    }
+
+    async fn list_payments(
+        &self,
+        request: Request<ListPaymentsRequest>,
+    ) -> Result<Response<ListPaymentsResponse>, Status> {
+        authorize(&request, Scope::ReadOnly)?;
+
+        let r = request.into_inner();
+
+        let direction = match r.direction() {
+            PaymentDirection::Unspecified => None,
+            PaymentDirection::Inbound => Some(ldk_node::PaymentDirection::Inbound),
+            PaymentDirection::Outbound => Some(ldk_node::PaymentDirection::Outbound),
+        };
+        let status = match r.status() {
+            PaymentStatus::Unspecified => None,
+            PaymentStatus::Pending => Some(ldk_node::PaymentStatus::Pending),
+            PaymentStatus::Succeeded => Some(ldk_node::PaymentStatus::Succeeded),
+            PaymentStatus::Failed => Some(ldk_node::PaymentStatus::Failed),
+        };
+        let from_timestamp = if r.from_timestamp > 0 {
+            Some(r.from_timestamp)
+        } else {
+            None
+        };
+        let to_timestamp = if r.to_timestamp > 0 {
+            Some(r.to_timestamp)
+        } else {
+            None
+        };
+
+        let payments = self
+            .node
+            .list_payments(direction, status, from_timestamp, to_timestamp);
+        let total = payments.len() as u64;
+        let limit = if r.limit > 0 {
+            r.limit as usize
+        } else {
+            usize::MAX
+        };
+
+        let payments: Vec<PaymentDetailsMessage> = payments
+            .iter()
+            .skip(r.offset as usize)
+            .take(limit)
+            .map(|(payment, created_at)| payment_details_message(payment, *created_at))
+            .collect();
+
+        Ok(Response::new(ListPaymentsResponse { payments, total }))
+    }
+
+    async fn get_payment(
+        &self,
+        request: Request<GetPaymentRequest>,
+    ) -> Result<Response<GetPaymentResponse>, Status> {
+        authorize(&request, Scope::ReadOnly)?;
+
+        let r = request.into_inner();
+        let payment_hash = parse_payment_hash(&r.payment_hash)?;
+
+        match self.node.get_payment(&payment_hash) {
+            Some(payment) => Ok(Response::new(GetPaymentResponse {
+                payment: Some(payment_details_message(
+                    &payment,
+                    self.node.payment_created_at(&payment),
+                )),
+            })),
+            None => Err(ApiError::not_found("Payment not found").into()),
+        }
+    }
+
+    async fn subscribe_events(
+        &self,
+        request: Request<SubscribeEventsRequest>,
+    ) -> Result<Response<Self::SubscribeEventsStream>, Status> {
+        authorize(&request, Scope::ReadOnly)?;
+
+        let r = request.into_inner();
+        let cursor = if r.cursor > 0 { Some(r.cursor) } else { None };
+        let event_types = r.event_types;
+        let accepts = move |event: &LightningNodeEvent| {
+            event_types.is_empty() || event_types.iter().any(|t| t == event.event_type())
+        };
+
+        let (replay, mut receiver) = match self.node.subscribe_event_feed(cursor) {
+            Ok(subscription) => subscription,
+            Err(err) => {
+                return Err(ApiError::new(
+                    Code::OutOfRange,
+                    ErrorReason::CursorExpired,
+                    err.to_string(),
+                )
+                .into())
+            }
+        };
+
+        let (sender, stream_receiver) = mpsc::channel(128);
+        tokio::spawn(async move {
+            for feed_event in replay {
+                if accepts(&feed_event.event) {
+                    let message = node_event_message(feed_event.sequence, &feed_event.event);
+                    if sender.send(message).await.is_err() {
+                        return;
+                    }
+                }
+            }
+
+            loop {
+                match receiver.recv().await {
+                    Ok(feed_event) => {
+                        if accepts(&feed_event.event) {
+                            let message =
+                                node_event_message(feed_event.sequence, &feed_event.event);
+                            if sender.send(message).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => {
+                        // Client resubscribes with the last cursor it received
+                        sender
+                            .send(Err(ApiError::new(
+                                Code::Aborted,
+                                ErrorReason::SubscriberLagged,
+                                "Subscriber lagged behind, resubscribe with the last cursor",
+                            )
+                            .into()))
+                            .await
+                            .ok();
+                        return;
+                    }
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        });
+
+        let stream = ReceiverStream::new(stream_receiver);
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn get_onchain_balance(
+        &self,
+        request: Request<()>,
+    ) -> Result<Response<GetOnchainBalanceResponse>, Status> {
+        authorize(&request, Scope::ReadOnly)?;
+
+        match self.node.get_onchain_balance() {
+            Ok((spendable_sats, total_sats)) => Ok(Response::new(GetOnchainBalanceResponse {
+                spendable_sats,
+                total_sats,
+            })),
+            Err(err) => Err(ApiError::from(err).into()),
+        }
+    }
+
+    async fn send_onchain(
+        &self,
+        request: Request<SendOnchainRequest>,
+    ) -> Result<Response<SendOnchainResponse>, Status> {
+        authorize(&request, Scope::Admin)?;
+
+        let r = request.into_inner();
+
+        let address = parse_address(&r.address, self.node.network())?;
+
+        let amount_sats = if r.send_all {
+            None
+        } else if r.amount_sats > 0 {
+            Some(r.amount_sats)
+        } else {
+            return Err(ApiError::invalid_amount().into());
+        };
+
+        match self.node.send_onchain(&address, amount_sats) {
+            Ok(txid) => Ok(Response::new(SendOnchainResponse {
+                txid: txid.to_string(),
+            })),
+            Err(err) => Err(ApiError::from(err).into()),
+        }
+    }
+
+    async fn list_utxos(
+        &self,
+        request: Request<()>,
+    ) -> Result<Response<ListUtxosResponse>, Status> {
+        authorize(&request, Scope::ReadOnly)?;
+
+        let utxos = match self.node.list_utxos() {
+            Ok(utxos) => utxos,
+            Err(err) => return Err(ApiError::from(err).into()),
+        };
+
+        let utxos: Vec<UtxoMessage> = utxos
+            .iter()
+            .filter(|u| !u.is_spent)
+            .map(|u| UtxoMessage {
+                outpoint: u.outpoint.to_string(),
+                amount_sats: u.txout.value,
+                script_pubkey: u.txout.script_pubkey.to_hex_string(),
+                is_change: u.keychain == KeychainKind::Internal,
+            })
+            .collect();
+
+        Ok(Response::new(ListUtxosResponse { utxos }))
+    }
+
+    async fn list_onchain_transactions(
+        &self,
+        request: Request<()>,
+    ) -> Result<Response<ListOnchainTransactionsResponse>, Status> {
+        authorize(&request, Scope::ReadOnly)?;
+
+        let transactions = match self.node.list_onchain_transactions() {
+            Ok(transactions) => transactions,
+            Err(err) => return Err(ApiError::from(err).into()),
+        };
+
+        let transactions: Vec<OnchainTransactionMessage> = transactions
+            .iter()
+            .map(|t| OnchainTransactionMessage {
+                txid: t.txid.to_string(),
+                received_sats: t.received,
+                sent_sats: t.sent,
+                fee_sats: t.fee.unwrap_or_default(),
+                confirmation_height: t
+                    .confirmation_time
+                    .as_ref()
+                    .map(|c| c.height)
+                    .unwrap_or_default(),
+                confirmation_timestamp: t
+                    .confirmation_time
+                    .as_ref()
+                    .map(|c| c.timestamp)
+                    .unwrap_or_default(),
+            })
+            .collect();
+
+        Ok(Response::new(ListOnchainTransactionsResponse {
+            transactions,
+        }))
+    }
+
+    async fn get_fee_estimates(
+        &self,
+        request: Request<()>,
+    ) -> Result<Response<GetFeeEstimatesResponse>, Status> {
+        authorize(&request, Scope::ReadOnly)?;
+
+        Ok(Response::new(GetFeeEstimatesResponse {
+            high_priority_sat_per_vbyte: sat_per_vbyte(
+                self.node
+                    .estimate_fee_rate(ConfirmationTarget::OnChainSweep),
+            ),
+            normal_sat_per_vbyte: sat_per_vbyte(
+                self.node
+                    .estimate_fee_rate(ConfirmationTarget::NonAnchorChannelFee),
+            ),
+            background_sat_per_vbyte: sat_per_vbyte(
+                self.node
+                    .estimate_fee_rate(ConfirmationTarget::ChannelCloseMinimum),
+            ),
+        }))
+    }
+
+    async fn connect_peer(
+        &self,
+        request: Request<ConnectPeerRequest>,
+    ) -> Result<Response<ConnectPeerResponse>, Status> {
+        authorize(&request, Scope::Admin)?;
+
+        let r = request.into_inner();
+        let node_id = parse_public_key(&r.node_id)?;
+        let address = parse_socket_address(&r.address)?;
+
+        match self.node.connect_peer(node_id, address, r.persist) {
+            Ok(_) => Ok(Response::new(ConnectPeerResponse {})),
+            Err(err) => Err(ApiError::from(err).into()),
+        }
+    }
+
+    async fn disconnect_peer(
+        &self,
+        request: Request<DisconnectPeerRequest>,
+    ) -> Result<Response<DisconnectPeerResponse>, Status> {
+        authorize(&request, Scope::Admin)?;
+
+        let r = request.into_inner();
+        let node_id = parse_public_key(&r.node_id)?;
+
+        if !self.node.get_peers().iter().any(|p| p.node_id == node_id) {
+            return Err(ApiError::not_found("Peer not found").into());
+        }
+
+        match self.node.disconnect_peer(node_id) {
+            Ok(_) => Ok(Response::new(DisconnectPeerResponse {})),
+            Err(err) => Err(ApiError::from(err).into()),
+        }
+    }
+
+    async fn list_peers(
+        &self,
+        request: Request<()>,
+    ) -> Result<Response<ListPeersResponse>, Status> {
+        authorize(&request, Scope::ReadOnly)?;
+
+        let peers: Vec<PeerDetailsMessage> = self
+            .node
+            .get_peers()
+            .iter()
+            .map(|p| PeerDetailsMessage {
+                node_id: p.node_id.to_string(),
+                address: p.address.to_string(),
+                is_persisted: p.is_persisted,
+                is_connected: p.is_connected,
+            })
+            .collect();
+
+        Ok(Response::new(ListPeersResponse { peers }))
+    }
+
+    async fn create_bolt12_offer(
+        &self,
+        request: Request<CreateBolt12OfferRequest>,
+    ) -> Result<Response<CreateBolt12OfferResponse>, Status> {
+        authorize(&request, Scope::Invoice)?;
+
+        let r = request.into_inner();
+        let amount_msat = if r.amount_msat > 0 {
+            Some(r.amount_msat)
+        } else {
+            None
+        };
+
+        match self.node.create_bolt12_offer(amount_msat, &r.description) {
+            Ok(offer) => Ok(Response::new(CreateBolt12OfferResponse {
+                offer: offer.to_string(),
+                offer_id: to_hex(&offer.id().0),
+            })),
+            Err(err) => Err(ApiError::from(err).into()),
+        }
+    }
+
+    async fn pay_bolt12_offer(
+        &self,
+        request: Request<PayBolt12OfferRequest>,
+    ) -> Result<Response<PayBolt12OfferResponse>, Status> {
+        authorize(&request, Scope::Payments)?;
+
+        let r = request.into_inner();
+        let offer = match Offer::from_str(&r.offer) {
+            Ok(offer) => offer,
+            Err(_) => return Err(ApiError::invalid_argument("Invalid offer").into()),
+        };
+
+        let amount_msat = if offer.amount().is_some() {
+            None
+        } else if r.amount_msat > 0 {
+            Some(r.amount_msat)
+        } else {
+            return Err(ApiError::invalid_amount().into());
+        };
+        let payer_note = if r.payer_note.is_empty() {
+            None
+        } else {
+            Some(r.payer_note)
+        };
+
+        match self.node.pay_bolt12_offer(&offer, amount_msat, payer_note) {
+            Ok(payment_id) => Ok(Response::new(PayBolt12OfferResponse {
+                payment_id: to_hex(&payment_id.0),
+            })),
+            Err(err) => Err(ApiError::from(err).into()),
+        }
+    }
+
+    async fn initiate_bolt12_refund(
+        &self,
+        request: Request<InitiateBolt12RefundRequest>,
+    ) -> Result<Response<InitiateBolt12RefundResponse>, Status> {
+        authorize(&request, Scope::Payments)?;
+
+        let r = request.into_inner();
+
+        match self
+            .node
+            .initiate_bolt12_refund(r.amount_msat, r.expiry_secs)
+        {
+            Ok(refund) => Ok(Response::new(InitiateBolt12RefundResponse {
+                refund: refund.to_string(),
+            })),
+            Err(err) => Err(ApiError::from(err).into()),
+        }
+    }
+
+    async fn request_bolt12_refund_payment(
+        &self,
+        request: Request<RequestBolt12RefundPaymentRequest>,
+    ) -> Result<Response<RequestBolt12RefundPaymentResponse>, Status> {
+        authorize(&request, Scope::Invoice)?;
+
+        let r = request.into_inner();
+        let refund = match Refund::from_str(&r.refund) {
+            Ok(refund) => refund,
+            Err(_) => return Err(ApiError::invalid_argument("Invalid refund").into()),
+        };
+
+        match self.node.request_bolt12_refund_payment(&refund) {
+            Ok(invoice) => Ok(Response::new(RequestBolt12RefundPaymentResponse {
+                invoice: to_hex(&invoice.encode()),
+            })),
+            Err(err) => Err(ApiError::from(err).into()),
+        }
+    }
+
+    async fn create_hold_invoice(
+        &self,
+        request: Request<CreateHoldInvoiceRequest>,
+    ) -> Result<Response<CreateHoldInvoiceResponse>, Status> {
+        authorize(&request, Scope::Invoice)?;
+
+        let r = request.into_inner();
+        let payment_hash = parse_payment_hash(&r.payment_hash)?;
+        let amount_msat = if r.amount_msat > 0 {
+            Some(r.amount_msat)
+        } else {
+            None
+        };
+
+        match self.node.create_hold_invoice(
+            payment_hash,
+            amount_msat,
+            &r.description,
+            r.expiry_secs,
+        ) {
+            Ok(invoice) => Ok(Response::new(CreateHoldInvoiceResponse {
+                invoice: invoice.to_string(),
+            })),
+            Err(err) => Err(ApiError::from(err).into()),
+        }
+    }
+
+    async fn settle_hold_invoice(
+        &self,
+        request: Request<SettleHoldInvoiceRequest>,
+    ) -> Result<Response<SettleHoldInvoiceResponse>, Status> {
+        authorize(&request, Scope::Invoice)?;
+
+        let r = request.into_inner();
+        let preimage = parse_preimage(&r.preimage)?;
+
+        match self.node.settle_hold_invoice(preimage) {
+            Ok(payment_hash) => Ok(Response::new(SettleHoldInvoiceResponse {
                 payment_hash: payment_hash.to_string(),
             })),
-            Err(err) => Err(Status::new(tonic::Code::Unknown, err.to_string())),
+            Err(err) => Err(ApiError::precondition(err).into()),
+        }
+    }
+
+    async fn cancel_hold_invoice(
+        &self,
+        request: Request<CancelHoldInvoiceRequest>,
+    ) -> Result<Response<CancelHoldInvoiceResponse>, Status> {
+        authorize(&request, Scope::Invoice)?;
+
+        let r = request.into_inner();
+        let payment_hash = parse_payment_hash(&r.payment_hash)?;
+
+        match self.node.cancel_hold_invoice(payment_hash) {
+            Ok(_) => Ok(Response::new(CancelHoldInvoiceResponse {})),
+            Err(err) => Err(ApiError::precondition(err).into()),
+        }
+    }
+
+    async fn estimate_route_fee(
+        &self,
+        request: Request<EstimateRouteFeeRequest>,
+    ) -> Result<Response<EstimateRouteFeeResponse>, Status> {
+        authorize(&request, Scope::ReadOnly)?;
+
+        let r = request.into_inner();
+        let amount_msat = if r.amount_msat > 0 {
+            Some(r.amount_msat)
+        } else {
+            None
+        };
+
+        let estimate = if !r.bolt11_invoice.is_empty() {
+            let bolt11_invoice = match Bolt11Invoice::from_str(&r.bolt11_invoice) {
+                Ok(invoice) => invoice,
+                Err(_) => return Err(ApiError::invalid_invoice().into()),
+            };
+            if bolt11_invoice.amount_milli_satoshis().is_none() && amount_msat.is_none() {
+                return Err(ApiError::invalid_amount().into());
+            }
+            self.node
+                .estimate_invoice_route_fee(&bolt11_invoice, amount_msat)
+                .await
+        } else if !r.destination.is_empty() {
+            let destination = parse_public_key(&r.destination)?;
+            let amount_msat = match amount_msat {
+                Some(amount_msat) => amount_msat,
+                None => return Err(ApiError::invalid_amount().into()),
+            };
+            self.node
+                .estimate_keysend_route_fee(destination, amount_msat)
+                .await
+        } else {
+            return Err(ApiError::invalid_argument(
+                "Either bolt11_invoice or destination is required",
+            )
+            .into());
+        };
+
+        match estimate {
+            Ok(estimate) => Ok(Response::new(EstimateRouteFeeResponse {
+                fee_msat: estimate.fee_msat,
+                success_likelihood: estimate.success_likelihood,
+                cached: estimate.cached,
+            })),
+            Err(err) => Err(ApiError::from(err).into()),
+        }
+    }
+
+    async fn update_channel_config(
+        &self,
+        request: Request<UpdateChannelConfigRequest>,
+    ) -> Result<Response<UpdateChannelConfigResponse>, Status> {
+        authorize(&request, Scope::Admin)?;
+
+        let r = request.into_inner();
+        let policy = channel_policy(r.policy)?;
+        let channel_id = if r.channel_id.is_empty() {
+            None
+        } else {
+            Some(parse_channel_id(&r.channel_id)?)
+        };
+
+        if let Some(channel_id) = channel_id {
+            if !self
+                .node
+                .get_channels()
+                .iter()
+                .any(|c| c.channel_id == channel_id)
+            {
+                return Err(ApiError::not_found("Channel not found").into());
+            }
+        }
+
+        match self.node.update_channel_config(channel_id, &policy) {
+            Ok(update) => Ok(Response::new(UpdateChannelConfigResponse {
+                channel_ids: update.updated.iter().map(|id| id.to_string()).collect(),
+                failed: update
+                    .failed
+                    .into_iter()
+                    .map(|(channel_id, error)| FailedChannelUpdate {
+                        channel_id: channel_id.to_string(),
+                        error,
+                    })
+                    .collect(),
+            })),
+            Err(err) => Err(ApiError::from(err).into()),
+        }
+    }
+
+    async fn create_jit_channel(
+        &self,
+        request: Request<CreateJitChannelRequest>,
+    ) -> Result<Response<CreateJitChannelResponse>, Status> {
+        authorize(&request, Scope::Invoice)?;
+
+        let r = request.into_inner();
+        let node_id = parse_public_key(&r.node_id)?;
+        let payment_size_msat = if r.payment_size_msat == 0 {
+            None
+        } else {
+            Some(r.payment_size_msat)
+        };
+
+        match self.node.create_jit_channel(node_id, payment_size_msat) {
+            Ok(channel) => Ok(Response::new(CreateJitChannelResponse {
+                intercept_scid: channel.intercept_scid,
+                lsp_node_id: self.node.get_id().to_string(),
+                cltv_expiry_delta: self.node.jit_channel_cltv_expiry_delta() as u32,
+                opening_fee_min_fee_msat: channel.fee_params.min_fee_msat,
+                opening_fee_proportional_millionths: channel.fee_params.proportional_millionths,
+                min_payment_size_msat: channel.fee_params.min_payment_size_msat,
+                max_payment_size_msat: channel.fee_params.max_payment_size_msat,
+                valid_until: channel.fee_params.valid_until,
+                opening_fee_msat: payment_size_msat
+                    .and_then(|size| channel.fee_params.opening_fee(size))
+                    .unwrap_or_default(),
+            })),
+            Err(err) if err.is::<JitChannelLimitReached>() => Err(ApiError::new(
+                Code::ResourceExhausted,
+                ErrorReason::ResourceExhausted,
+                err.to_string(),
+            )
+            .into()),
+            Err(err) => Err(ApiError::from(err).into()),
+        }
+    }
+
+    async fn sign_message(
+        &self,
+        request: Request<SignMessageRequest>,
+    ) -> Result<Response<SignMessageResponse>, Status> {
+        authorize(&request, Scope::Admin)?;
+
+        let r = request.into_inner();
+        match self.node.sign_message(&r.message) {
+            Ok(signature) => Ok(Response::new(SignMessageResponse { signature })),
+            Err(err) => Err(ApiError::from(err).into()),
+        }
+    }
+
+    async fn verify_signature(
+        &self,
+        request: Request<VerifySignatureRequest>,
+    ) -> Result<Response<VerifySignatureResponse>, Status> {
+        authorize(&request, Scope::ReadOnly)?;
+
+        let r = request.into_inner();
+        let expected_node_id = if r.node_id.is_empty() {
+            None
+        } else {
+            Some(parse_public_key(&r.node_id)?)
+        };
+
+        let signer = match self.node.recover_signer(&r.message, &r.signature) {
+            Some(signer) => signer,
+            None => {
+                return Ok(Response::new(VerifySignatureResponse {
+                    valid: false,
+                    node_id: "".to_string(),
+                }))
+            }
+        };
+
+        let node_id = expected_node_id.unwrap_or(signer);
+        Ok(Response::new(VerifySignatureResponse {
+            valid: self
+                .node
+                .verify_signature(&r.message, &r.signature, &node_id),
+            node_id: signer.to_string(),
+        }))
+    }
+
+    async fn new_address(
+        &self,
+        request: Request<()>,
+    ) -> Result<Response<NewAddressResponse>, Status> {
+        authorize(&request, Scope::Invoice)?;
+
+        match self.node.new_onchain_address() {
+            Ok(address) => Ok(Response::new(NewAddressResponse {
+                address: address.to_string(),
+            })),
+            Err(err) => Err(ApiError::from(err).into()),
         }
     }
 }
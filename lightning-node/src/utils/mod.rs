@@ -0,0 +1,20 @@
+use std::{
+    fmt::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut res = String::with_capacity(2 * bytes.len());
+    for b in bytes {
+        write!(&mut res, "{:02x}", b).expect("Unable to write");
+    }
+    res
+}
+
+/// Seconds since the unix epoch, as LDK timestamps payments.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
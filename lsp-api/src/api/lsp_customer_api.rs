@@ -5,8 +5,11 @@ use axum::{
 };
 use database::surrealdb::engine::remote::ws::Client;
 use lightning_invoice::Bolt11Invoice;
-use lightning_node_client::{get_lightning_node_client, proto::CreateBolt11InvoiceRequest};
-use log::info;
+use lightning_node_client::{
+    error_reason, get_lightning_node_client,
+    proto::{CreateBolt11InvoiceRequest, CreateJitChannelRequest, ErrorReason},
+};
+use log::{info, warn};
 use std::{str::FromStr, sync::Arc};
 
 use crate::{
@@ -15,7 +18,9 @@ use crate::{
     services::lsp_customer_service::LspCustomerService,
 };
 
-use super::models::{GetInvoiceParams, GetInvoiceResponse, LspSignUpRequest};
+use super::models::{
+    GetInvoiceParams, GetInvoiceResponse, JitChannelParams, JitChannelResponse, LspSignUpRequest,
+};
 
 pub async fn lsp_signup(
     lsp_customer_service: Extension<Arc<LspCustomerService<Client>>>,
@@ -82,9 +87,13 @@ pub async fn get_invoice(
         .await
         .unwrap();
 
-    let mut node_client = get_lightning_node_client(config.lightning_node_endpoint.clone(), true)
-        .await
-        .unwrap();
+    let mut node_client = get_lightning_node_client(
+        config.lightning_node_endpoint.clone(),
+        true,
+        &config.lightning_node_credentials(),
+    )
+    .await
+    .unwrap();
 
     let invoice_res = node_client
         .create_bolt11_invoice(CreateBolt11InvoiceRequest {
@@ -110,3 +119,67 @@ pub async fn get_invoice(
         routes: vec![],
     }))
 }
+
+pub async fn create_jit_channel(
+    lsp_customer_service: Extension<Arc<LspCustomerService<Client>>>,
+    config: Extension<LspConfig>,
+    query: Query<JitChannelParams>,
+    Path(alias): Path<String>,
+) -> Result<Json<JitChannelResponse>, StatusCode> {
+    let customer = match lsp_customer_service.get_customer_by_alias(alias).await {
+        Some(customer) => customer,
+        None => return Err(StatusCode::NOT_FOUND),
+    };
+    let node_id = match customer.node_id {
+        Some(node_id) => node_id,
+        None => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let mut node_client = match get_lightning_node_client(
+        config.lightning_node_endpoint.clone(),
+        true,
+        &config.lightning_node_credentials(),
+    )
+    .await
+    {
+        Ok(client) => client,
+        Err(_) => return Err(StatusCode::SERVICE_UNAVAILABLE),
+    };
+
+    match node_client
+        .create_jit_channel(CreateJitChannelRequest {
+            node_id,
+            payment_size_msat: query.amount.unwrap_or(0),
+        })
+        .await
+    {
+        Ok(res) => {
+            let res = res.into_inner();
+            info!(
+                "JIT channel with SCID {} sold to {}",
+                res.intercept_scid, customer.alias
+            );
+            Ok(Json(JitChannelResponse {
+                lsp_node_id: res.lsp_node_id,
+                intercept_scid: res.intercept_scid,
+                cltv_expiry_delta: res.cltv_expiry_delta,
+                opening_fee_min_fee_msat: res.opening_fee_min_fee_msat,
+                opening_fee_proportional_millionths: res.opening_fee_proportional_millionths,
+                min_payment_size_msat: res.min_payment_size_msat,
+                max_payment_size_msat: res.max_payment_size_msat,
+                valid_until: res.valid_until,
+                opening_fee_msat: res.opening_fee_msat,
+            }))
+        }
+        Err(err) => {
+            warn!("Cannot create JIT channel: {}", err.message());
+            match error_reason(&err) {
+                ErrorReason::InvalidAmount | ErrorReason::InvalidArgument => {
+                    Err(StatusCode::BAD_REQUEST)
+                }
+                ErrorReason::ResourceExhausted => Err(StatusCode::TOO_MANY_REQUESTS),
+                _ => Err(StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        }
+    }
+}
@@ -21,9 +21,28 @@ pub struct GetInvoiceResponse {
     pub routes: Vec<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JitChannelParams {
+    /// Amount of the invoice the customer creates, omit for invoices without amount
+    pub amount: Option<u64>,
+}
+
+/// Route hint and LSPS2 fee terms of a JIT channel, the customer adds the hint to its invoice
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JitChannelResponse {
+    pub lsp_node_id: String,
+    pub intercept_scid: u64,
+    pub cltv_expiry_delta: u32,
+    pub opening_fee_min_fee_msat: u64,
+    pub opening_fee_proportional_millionths: u32,
+    pub min_payment_size_msat: u64,
+    pub max_payment_size_msat: u64,
+    pub valid_until: u64,
+    pub opening_fee_msat: u64,
+}
+
 // Nostr
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Nip05Params {
     pub name: Option<String>,
 }
-
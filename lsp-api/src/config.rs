@@ -1,3 +1,4 @@
+use lightning_node_client::GrpcCredentials;
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug, Clone)]
@@ -8,4 +9,32 @@ pub struct LspConfig {
     pub default_cashu_endpoint: String,
     pub lsp_cashu_mint: String,
     pub lsp_api_port: u16,
-}
\ No newline at end of file
+    pub lightning_node_auth_token: Option<String>,
+    pub lightning_node_tls_cert: Option<String>,
+    pub lightning_node_tls_client_cert: Option<String>,
+    pub lightning_node_tls_client_key: Option<String>,
+    pub cashu_auth_token: Option<String>,
+    pub cashu_tls_cert: Option<String>,
+    pub cashu_tls_client_cert: Option<String>,
+    pub cashu_tls_client_key: Option<String>,
+}
+
+impl LspConfig {
+    pub fn lightning_node_credentials(&self) -> GrpcCredentials {
+        GrpcCredentials {
+            auth_token: self.lightning_node_auth_token.clone(),
+            tls_cert_path: self.lightning_node_tls_cert.clone(),
+            tls_client_cert_path: self.lightning_node_tls_client_cert.clone(),
+            tls_client_key_path: self.lightning_node_tls_client_key.clone(),
+        }
+    }
+
+    pub fn cashu_credentials(&self) -> GrpcCredentials {
+        GrpcCredentials {
+            auth_token: self.cashu_auth_token.clone(),
+            tls_cert_path: self.cashu_tls_cert.clone(),
+            tls_client_cert_path: self.cashu_tls_client_cert.clone(),
+            tls_client_key_path: self.cashu_tls_client_key.clone(),
+        }
+    }
+}
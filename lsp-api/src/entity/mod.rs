@@ -22,6 +22,31 @@ pub struct LspInvoice {
     pub expiration: Datetime,
 }
 
+/// Keysend payment to a customer which was still in flight when the received payment was
+/// handled, it's paid out another way if it fails.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LspPendingKeysend {
+    #[allow(dead_code)]
+    pub id: Option<Thing>,
+    pub payment_hash: String,
+    /// Hash of the payment the customer was paid through
+    pub original_payment_hash: String,
+    pub alias: String,
+    pub amount_msat: u64,
+}
+
+/// Token minted to pay out a received payment, kept so a retry sends it again instead of
+/// minting another one.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LspPayoutToken {
+    #[allow(dead_code)]
+    pub id: Option<Thing>,
+    /// Hash of the payment the token pays out
+    pub original_payment_hash: String,
+    pub alias: String,
+    pub token: String,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct LspCustomerConfig {
     pub min_channel_size_sat: u64,
@@ -5,13 +5,13 @@ use axum::{
     routing::{get, post, put},
     Extension, Router,
 };
-use database::{config::SurrealDbConfig, init_db};
+use database::{config::SurrealDbConfig, init_db, processed_events::ProcessedEventStore};
 use dotenv::dotenv;
-use events::config::RabbitMqConfig;
 use lightning_node_client::get_lightning_node_client;
 use log::info;
 use repository::{
     lsp_customer_repository::LspCustomerRepository, lsp_invoice_repository::LspInvoiceRepository,
+    lsp_keysend_repository::LspKeysendRepository, lsp_token_repository::LspTokenRepository,
 };
 use services::lsp_customer_service::LspCustomerService;
 
@@ -31,12 +31,15 @@ async fn main() -> Result<()> {
     env_logger::init();
 
     let config = envy::from_env::<LspConfig>().unwrap();
-    let rabbitmq_config = envy::from_env::<RabbitMqConfig>().unwrap();
     let db_config = envy::from_env::<SurrealDbConfig>().unwrap();
 
-    let events = events::lightning_node_events::LightningNodeEvents::new(rabbitmq_config).await?;
-    let node_client =
-        get_lightning_node_client(config.lightning_node_endpoint.clone(), true).await?;
+    let events = events::event_bus::from_env().await?;
+    let node_client = get_lightning_node_client(
+        config.lightning_node_endpoint.clone(),
+        true,
+        &config.lightning_node_credentials(),
+    )
+    .await?;
 
     let nostr_client = client::nostr_client::NostrClient::from_mnemonic(&config.mnemonic, None);
     nostr_client
@@ -49,17 +52,25 @@ async fn main() -> Result<()> {
     
     let customer_repo = LspCustomerRepository::new(database.clone());
     let invoice_repo = LspInvoiceRepository::new(database.clone());
+    let keysend_repo = LspKeysendRepository::new(database.clone());
+    let token_repo = LspTokenRepository::new(database.clone());
     
     let lsp_service = Arc::new(LspCustomerService::new(
         customer_repo,
         invoice_repo,
+        keysend_repo,
+        token_repo,
         config.default_cashu_endpoint.clone(),
+        config.cashu_credentials(),
         config.lsp_cashu_mint.clone(),
         nostr_client,
     ));
     
     let payment_received_service = PaymentReceivedService::new(node_client, lsp_service.clone());
-    payment_received_service.subscribe(events);
+    payment_received_service.subscribe(
+        events.clone(),
+        Arc::new(ProcessedEventStore::new(database.clone())),
+    );
 
     info!(
         "Starting rest api server at 0.0.0.0:{}",
@@ -73,8 +84,14 @@ async fn main() -> Result<()> {
             "/api/lsp/invoice/:alias",
             get(api::lsp_customer_api::get_invoice),
         )
+        .route(
+            "/api/lsp/jit-channel/:alias",
+            post(api::lsp_customer_api::create_jit_channel),
+        )
         .route("/.well-known/nostr.json", get(api::nostr_api::nip05))
+        .route("/health", get(events::health::health))
         .layer(Extension(lsp_service))
+        .layer(Extension(events))
         .layer(Extension(config.clone()));
 
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", config.lsp_api_port))
@@ -0,0 +1,103 @@
+use anyhow::Result;
+
+use database::surrealdb::{
+    sql::{Id, Thing},
+    Connection, Surreal,
+};
+
+use crate::entity::LspPendingKeysend;
+
+const PENDING_KEYSEND_TABLE: &str = "pending_keysend";
+
+pub struct LspKeysendRepository<C>
+where
+    C: Connection,
+{
+    db: Surreal<C>,
+}
+
+impl<C> LspKeysendRepository<C>
+where
+    C: Connection,
+{
+    pub fn new(db: Surreal<C>) -> Self {
+        Self { db }
+    }
+
+    pub async fn add_pending_keysend(&self, keysend: LspPendingKeysend) -> Result<()> {
+        let mut keysend = keysend;
+        keysend.id = Some(Thing {
+            tb: PENDING_KEYSEND_TABLE.to_string(),
+            id: Id::String(keysend.payment_hash.clone()),
+        });
+
+        let _: Vec<LspPendingKeysend> = self
+            .db
+            .create(PENDING_KEYSEND_TABLE)
+            .content(keysend)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_pending_keysend(
+        &self,
+        payment_hash: &str,
+    ) -> Result<Option<LspPendingKeysend>> {
+        let keysend = self
+            .db
+            .select((PENDING_KEYSEND_TABLE, payment_hash))
+            .await?;
+
+        Ok(keysend)
+    }
+
+    pub async fn remove_pending_keysend(&self, payment_hash: &str) -> Result<()> {
+        let _: Option<LspPendingKeysend> = self
+            .db
+            .delete((PENDING_KEYSEND_TABLE, payment_hash))
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::surrealdb::engine::local::Mem;
+    use database::surrealdb::Surreal;
+
+    use crate::entity::LspPendingKeysend;
+
+    use super::LspKeysendRepository;
+
+    #[tokio::test]
+    async fn test_pending_keysend() {
+        let db = Surreal::new::<Mem>(()).await.unwrap();
+        db.use_ns("test").await.unwrap();
+        db.use_db("test").await.unwrap();
+
+        let repository = LspKeysendRepository { db };
+
+        repository
+            .add_pending_keysend(LspPendingKeysend {
+                id: None,
+                payment_hash: "keysend".to_string(),
+                original_payment_hash: "invoice".to_string(),
+                alias: "fake alias".to_string(),
+                amount_msat: 1000,
+            })
+            .await
+            .unwrap();
+
+        let keysend = repository.get_pending_keysend("keysend").await.unwrap();
+        assert_eq!(keysend.unwrap().amount_msat, 1000);
+
+        repository.remove_pending_keysend("keysend").await.unwrap();
+        assert!(repository
+            .get_pending_keysend("keysend")
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
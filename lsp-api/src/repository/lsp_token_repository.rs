@@ -0,0 +1,87 @@
+use anyhow::Result;
+
+use database::surrealdb::{
+    sql::{Id, Thing},
+    Connection, Surreal,
+};
+
+use crate::entity::LspPayoutToken;
+
+const PAYOUT_TOKEN_TABLE: &str = "payout_token";
+
+pub struct LspTokenRepository<C>
+where
+    C: Connection,
+{
+    db: Surreal<C>,
+}
+
+impl<C> LspTokenRepository<C>
+where
+    C: Connection,
+{
+    pub fn new(db: Surreal<C>) -> Self {
+        Self { db }
+    }
+
+    pub async fn add_payout_token(&self, token: LspPayoutToken) -> Result<()> {
+        let mut token = token;
+        token.id = Some(Thing {
+            tb: PAYOUT_TOKEN_TABLE.to_string(),
+            id: Id::String(token.original_payment_hash.clone()),
+        });
+
+        let _: Vec<LspPayoutToken> = self.db.create(PAYOUT_TOKEN_TABLE).content(token).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_payout_token(
+        &self,
+        original_payment_hash: &str,
+    ) -> Result<Option<LspPayoutToken>> {
+        let token = self
+            .db
+            .select((PAYOUT_TOKEN_TABLE, original_payment_hash))
+            .await?;
+
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::surrealdb::engine::local::Mem;
+    use database::surrealdb::Surreal;
+
+    use crate::entity::LspPayoutToken;
+
+    use super::LspTokenRepository;
+
+    #[tokio::test]
+    async fn test_payout_token() {
+        let db = Surreal::new::<Mem>(()).await.unwrap();
+        db.use_ns("test").await.unwrap();
+        db.use_db("test").await.unwrap();
+
+        let repository = LspTokenRepository { db };
+        let token = || LspPayoutToken {
+            id: None,
+            original_payment_hash: "invoice".to_string(),
+            alias: "fake alias".to_string(),
+            token: "cashuA".to_string(),
+        };
+
+        repository.add_payout_token(token()).await.unwrap();
+        let stored = repository.get_payout_token("invoice").await.unwrap();
+        assert_eq!(stored.unwrap().token, "cashuA");
+
+        // A payment is paid out with one token only
+        assert!(repository.add_payout_token(token()).await.is_err());
+        assert!(repository
+            .get_payout_token("other")
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -1,2 +1,4 @@
 pub mod  lsp_customer_repository;
-pub mod lsp_invoice_repository;
\ No newline at end of file
+pub mod lsp_invoice_repository;
+pub mod lsp_keysend_repository;
+pub mod lsp_token_repository;
\ No newline at end of file
@@ -1,32 +1,40 @@
 use anyhow::{bail, Result};
-use bitcoin::PublicKey;
-use cashu_internal_client::{get_cashu_client, proto::InternalTokenMintRequest};
+use bitcoin::{hex::FromHex, PublicKey};
+use cashu_internal_client::{get_cashu_client, proto::InternalTokenMintRequest, GrpcCredentials};
 use chrono::Utc;
 use database::surrealdb::{sql::Datetime, Connection};
 use lightning_invoice::Bolt11Invoice;
-use lightning_node_client::proto::{
-    node_client::NodeClient, OpenChannelRequest, SendKeysendPaymentRequest,
+use lightning_node_client::{
+    error_reason,
+    proto::{CustomTlvRecord, OpenChannelRequest, PaymentStatus, SendKeysendPaymentRequest},
+    LightningNodeClient,
 };
 use log::{debug, info, warn};
-use tonic::transport::Channel;
 
 use crate::{
     client::nostr_client::NostrClient,
-    entity::{LspCustomer, LspCustomerConfig, LspInvoice},
+    entity::{LspCustomer, LspCustomerConfig, LspInvoice, LspPayoutToken, LspPendingKeysend},
     repository::{
         lsp_customer_repository::LspCustomerRepository,
-        lsp_invoice_repository::LspInvoiceRepository,
+        lsp_invoice_repository::LspInvoiceRepository, lsp_keysend_repository::LspKeysendRepository,
+        lsp_token_repository::LspTokenRepository,
     },
     utils,
 };
 
+/// Custom keysend record carrying the 32 byte hash of the invoice the customer was paid through.
+const ORIGINAL_PAYMENT_HASH_TLV_TYPE: u64 = 696969;
+
 pub struct LspCustomerService<C>
 where
     C: Connection,
 {
     repository: LspCustomerRepository<C>,
     invoice_repository: LspInvoiceRepository<C>,
+    keysend_repository: LspKeysendRepository<C>,
+    token_repository: LspTokenRepository<C>,
     walletka_bank_endpoint: String,
+    cashu_credentials: GrpcCredentials,
     cashu_mint: String,
     nostr_client: NostrClient,
 }
@@ -38,14 +46,20 @@ where
     pub fn new(
         repository: LspCustomerRepository<C>,
         invoice_repository: LspInvoiceRepository<C>,
+        keysend_repository: LspKeysendRepository<C>,
+        token_repository: LspTokenRepository<C>,
         walletka_bank_endpoint: String,
+        cashu_credentials: GrpcCredentials,
         cashu_mint: String,
         nostr_client: NostrClient,
     ) -> Self {
         Self {
             repository,
             invoice_repository,
+            keysend_repository,
+            token_repository,
             walletka_bank_endpoint,
+            cashu_credentials,
             cashu_mint,
             nostr_client,
         }
@@ -129,7 +143,7 @@ where
 
     pub async fn handle_paid_invoice(
         &self,
-        node_client: &mut NodeClient<Channel>,
+        node_client: &mut LightningNodeClient,
         payment_hash: String,
         amount_msat: u64,
     ) -> Result<()> {
@@ -150,54 +164,61 @@ where
         if customer.node_id.is_some() {
             let node_id = customer.node_id.clone().unwrap();
 
+            let original_payment_hash = <[u8; 32]>::from_hex(&payment_hash)?;
             info!("Sending keysend payment to {}", customer.alias);
-            match node_client
+            let keysend = match node_client
                 .send_keysend_payment(SendKeysendPaymentRequest {
                     destination: node_id.clone(),
                     amount: amount_msat,
+                    custom_records: vec![CustomTlvRecord {
+                        tlv_type: ORIGINAL_PAYMENT_HASH_TLV_TYPE,
+                        value: original_payment_hash.to_vec(),
+                    }],
                 })
                 .await
             {
-                Ok(_) => {
-                    //let res = res.into_inner();
+                Ok(res) => Some(res.into_inner()),
+                Err(err) => {
+                    warn!(
+                        "Keysend payment to {} failed: {:?}, {}",
+                        customer.alias,
+                        error_reason(&err),
+                        err.message()
+                    );
+                    None
+                }
+            };
+
+            match keysend {
+                Some(keysend) if keysend.status() == PaymentStatus::Succeeded => {
                     info!("Keysend payment sent!");
                     Ok(())
                 }
-                Err(_) => {
-                    warn!("Keysend payment failed"); // Todo check reason
-                    if amount_msat > customer.config.min_channel_size_sat * 1000 {
-                        info!(
-                            "Opening channel to {} with push amount {} sats",
-                            &customer.alias, amount_msat
-                        );
-
-                        match node_client
-                            .open_channel(OpenChannelRequest {
-                                node_id: node_id.clone(),
-                                address: "".to_string(),
-                                channel_amount_sats: (amount_msat / 1000) * 12 / 10, // Open channel with requested amount + 20%
-                                push_to_counterparty_msat: amount_msat,
-                                public: customer.config.public_channels,
-                            })
-                            .await
-                        {
-                            Ok(_) => {
-                                info!("Channel to {} openned successfully", customer.alias.clone());
-                                Ok(())
-                            }
-                            Err(_) => {
-                                self.mint_and_send_token(
-                                    &customer,
-                                    self.cashu_mint.clone(),
-                                    amount_msat,
-                                )
-                                .await
-                            }
-                        }
-                    } else {
-                        self.mint_and_send_token(&customer, self.cashu_mint.clone(), amount_msat)
-                            .await
-                    }
+                // The node stops waiting for the payment after a while, its outcome arrives as
+                // a PaymentSuccessful or PaymentFailed event. Waiting for it here would hold up
+                // all other received payments.
+                Some(keysend) if keysend.status() == PaymentStatus::Pending => {
+                    info!("Keysend payment to {} is in flight", customer.alias);
+                    self.keysend_repository
+                        .add_pending_keysend(LspPendingKeysend {
+                            id: None,
+                            payment_hash: keysend.payment_hash,
+                            original_payment_hash: payment_hash,
+                            alias: customer.alias.clone(),
+                            amount_msat,
+                        })
+                        .await
+                }
+                _ => {
+                    warn!("Keysend payment failed");
+                    self.pay_out_without_keysend(
+                        node_client,
+                        &customer,
+                        &node_id,
+                        &payment_hash,
+                        amount_msat,
+                    )
+                    .await
                 }
             }
         } else {
@@ -205,31 +226,164 @@ where
         }
     }
 
-    async fn mint_and_send_token(
+    /// Forgets a keysend payment which was in flight when its received payment was handled.
+    pub async fn handle_keysend_succeeded(&self, payment_hash: &str) -> Result<()> {
+        if let Some(keysend) = self
+            .keysend_repository
+            .get_pending_keysend(payment_hash)
+            .await?
+        {
+            info!("Keysend payment to {} succeeded", keysend.alias);
+            self.keysend_repository
+                .remove_pending_keysend(payment_hash)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Pays out a keysend payment which failed after its received payment was handled.
+    pub async fn handle_keysend_failed(
         &self,
-        lsp_customer: &LspCustomer,
-        mint_id: String,
-        amount_msat: u64,
+        node_client: &mut LightningNodeClient,
+        payment_hash: &str,
     ) -> Result<()> {
-        let mut cashu_client = get_cashu_client(self.walletka_bank_endpoint.clone(), false)
+        let keysend = match self
+            .keysend_repository
+            .get_pending_keysend(payment_hash)
+            .await?
+        {
+            Some(keysend) => keysend,
+            None => return Ok(()),
+        };
+        let customer = match self
+            .repository
+            .get_customer_by_alias(keysend.alias.clone())
+            .await?
+        {
+            Some(customer) => customer,
+            None => bail!(
+                "Customer {} of keysend {} not found",
+                keysend.alias,
+                payment_hash
+            ),
+        };
+        let node_id = match &customer.node_id {
+            Some(node_id) => node_id.clone(),
+            None => bail!("Customer {} is missing node id!", customer.alias),
+        };
+
+        warn!(
+            "Keysend payment to {} for {} failed",
+            customer.alias, keysend.original_payment_hash
+        );
+        self.pay_out_without_keysend(
+            node_client,
+            &customer,
+            &node_id,
+            &keysend.original_payment_hash,
+            keysend.amount_msat,
+        )
+        .await?;
+        self.keysend_repository
+            .remove_pending_keysend(payment_hash)
             .await
-            .unwrap();
+    }
 
-        let res = cashu_client
-            .internal_token_mint(InternalTokenMintRequest {
-                amount_sat: amount_msat,
-                service_name: "walletka-lsp".to_string(),
-                mint_id,
-            })
+    /// Opens a channel pushing the amount to the customer, or sends a token if the amount is
+    /// too small for a channel or opening fails.
+    async fn pay_out_without_keysend(
+        &self,
+        node_client: &mut LightningNodeClient,
+        customer: &LspCustomer,
+        node_id: &str,
+        original_payment_hash: &str,
+        amount_msat: u64,
+    ) -> Result<()> {
+        if amount_msat > customer.config.min_channel_size_sat * 1000 {
+            info!(
+                "Opening channel to {} with push amount {} sats",
+                &customer.alias, amount_msat
+            );
+
+            match node_client
+                .open_channel(OpenChannelRequest {
+                    node_id: node_id.to_string(),
+                    address: "".to_string(),
+                    channel_amount_sats: (amount_msat / 1000) * 12 / 10, // Open channel with requested amount + 20%
+                    push_to_counterparty_msat: amount_msat,
+                    public: customer.config.public_channels,
+                    policy: None,
+                })
+                .await
+            {
+                Ok(_) => {
+                    info!("Channel to {} openned successfully", customer.alias.clone());
+                    Ok(())
+                }
+                Err(_) => {
+                    self.mint_and_send_token(
+                        customer,
+                        self.cashu_mint.clone(),
+                        original_payment_hash,
+                        amount_msat,
+                    )
+                    .await
+                }
+            }
+        } else {
+            self.mint_and_send_token(
+                customer,
+                self.cashu_mint.clone(),
+                original_payment_hash,
+                amount_msat,
+            )
             .await
-            .unwrap()
-            .into_inner();
+        }
+    }
 
+    /// Sends the customer a token for the received payment, minting it only the first time.
+    ///
+    /// The minted token is stored before it's sent, so a retry after the token couldn't be sent
+    /// sends the same token again instead of minting another one.
+    async fn mint_and_send_token(
+        &self,
+        lsp_customer: &LspCustomer,
+        mint_id: String,
+        original_payment_hash: &str,
+        amount_msat: u64,
+    ) -> Result<()> {
         let customer_npub = match lsp_customer.npub.clone() {
             Some(npub) => npub,
             None => bail!("Customer {} is missing npub!", lsp_customer.alias),
         };
 
+        let token = match self
+            .token_repository
+            .get_payout_token(original_payment_hash)
+            .await?
+        {
+            Some(token) => {
+                info!(
+                    "Resending token minted for {} to {}",
+                    original_payment_hash, lsp_customer.alias
+                );
+                token.token
+            }
+            None => {
+                let token = self.mint_token(lsp_customer, mint_id, amount_msat).await?;
+                self.token_repository
+                    .add_payout_token(LspPayoutToken {
+                        id: None,
+                        original_payment_hash: original_payment_hash.to_string(),
+                        alias: lsp_customer.alias.clone(),
+                        token: token.clone(),
+                    })
+                    .await?;
+                token
+            }
+        };
+
         info!(
             "Sending token to {} over nostr using npub {}",
             lsp_customer.alias, customer_npub
@@ -237,11 +391,45 @@ where
 
         match self
             .nostr_client
-            .send_message(customer_npub, res.token.as_str())
+            .send_message(customer_npub, token.as_str())
             .await
         {
             Ok(_) => Ok(()),
             Err(err) => bail!(err),
         }
     }
+
+    async fn mint_token(
+        &self,
+        lsp_customer: &LspCustomer,
+        mint_id: String,
+        amount_msat: u64,
+    ) -> Result<String> {
+        let mut cashu_client = get_cashu_client(
+            self.walletka_bank_endpoint.clone(),
+            false,
+            &self.cashu_credentials,
+        )
+        .await?;
+
+        // Tokens only have whole sat denominations, the remainder is rounded down
+        let amount_sat = amount_msat / 1000;
+        if amount_msat % 1000 != 0 {
+            info!(
+                "Rounding {}msat down to {}sat for {}",
+                amount_msat, amount_sat, lsp_customer.alias
+            );
+        }
+
+        let res = cashu_client
+            .internal_token_mint(InternalTokenMintRequest {
+                amount_sat,
+                service_name: "walletka-lsp".to_string(),
+                mint_id,
+            })
+            .await?
+            .into_inner();
+
+        Ok(res.token)
+    }
 }
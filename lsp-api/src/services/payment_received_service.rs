@@ -2,19 +2,26 @@ use std::sync::Arc;
 
 use anyhow::Result;
 use database::surrealdb::Connection;
-use events::lightning_node_events::{LightningNodeEvents, PaymentReceivedProcessor};
-use lightning_node_client::proto::node_client::NodeClient;
+use events::{
+    event_bus::EventBus,
+    idempotency::{Deduplicated, IdempotencyStore},
+    lightning_node_events::LightningNodeEventHandler,
+    messages::CustomTlvRecord,
+};
+use lightning_node_client::LightningNodeClient;
 use log::{info, warn};
-use tonic::{async_trait, transport::Channel};
+use tonic::async_trait;
 
 use super::lsp_customer_service::LspCustomerService;
 
-#[derive(Clone)]
-struct PaymentReceivedCallback<C>
+const RECEIVED_PAYMENTS_QUEUE: &str = "walletka.lsp.received_payments";
+
+/// Forwards received payments to customers and follows up on keysend payments to them.
+struct PaymentEventsHandler<C>
 where
     C: Connection,
 {
-    client: NodeClient<Channel>,
+    client: LightningNodeClient,
     lsp_customer_service: Arc<LspCustomerService<C>>,
 }
 
@@ -22,7 +29,7 @@ pub struct PaymentReceivedService<C>
 where
     C: Connection,
 {
-    client: NodeClient<Channel>,
+    client: LightningNodeClient,
     lsp_customer_service: Arc<LspCustomerService<C>>,
 }
 
@@ -31,7 +38,7 @@ where
     C: Connection,
 {
     pub fn new(
-        client: NodeClient<Channel>,
+        client: LightningNodeClient,
         lsp_customer_service: Arc<LspCustomerService<C>>,
     ) -> Self {
         Self {
@@ -40,17 +47,32 @@ where
         }
     }
 
-    pub fn subscribe(&self, events: LightningNodeEvents) {
-        let callback = PaymentReceivedCallback {
-            client: self.client.clone(),
-            lsp_customer_service: self.lsp_customer_service.clone(),
-        };
+    /// Handles every received payment once, redelivered events are skipped so a payment is
+    /// never forwarded to the customer twice. Keysend payments still in flight are paid out
+    /// another way once their PaymentFailed event arrives.
+    ///
+    /// A payment whose forwarding failed is retried, a token minted for it is sent again instead
+    /// of minting another one. A payment whose forwarding was interrupted is handled again once
+    /// its claim expires after `CLAIM_LEASE`.
+    pub fn subscribe(
+        &self,
+        events: Arc<dyn EventBus>,
+        processed_events: Arc<dyn IdempotencyStore + Send + Sync>,
+    ) {
+        let handler = Deduplicated::new(
+            RECEIVED_PAYMENTS_QUEUE,
+            Arc::new(PaymentEventsHandler {
+                client: self.client.clone(),
+                lsp_customer_service: self.lsp_customer_service.clone(),
+            }),
+            processed_events,
+        );
 
         tokio::spawn(async move {
             info!("Subscribing lightning payments");
 
             events
-                .subscribe_received_payments("walletka.lsp.received_payments", callback)
+                .subscribe(RECEIVED_PAYMENTS_QUEUE, Arc::new(handler))
                 .await
                 .unwrap();
 
@@ -60,20 +82,28 @@ where
 }
 
 #[async_trait]
-impl<C> PaymentReceivedProcessor for PaymentReceivedCallback<C>
+impl<C> LightningNodeEventHandler for PaymentEventsHandler<C>
 where
     C: Connection,
 {
-    async fn payment_received_callback(
+    async fn payment_received(
         &self,
         payment_hash: String,
         amount_msat: u64,
+        offer_id: Option<String>,
+        custom_records: Vec<CustomTlvRecord>,
     ) -> Result<(), anyhow::Error> {
         let mut client = self.client.clone();
 
         info!("Payment received!");
         info!("Payment hash: {}", payment_hash);
         info!("Amount: {}msat", amount_msat);
+        if let Some(offer_id) = &offer_id {
+            info!("Offer id: {}", offer_id);
+        }
+        for record in &custom_records {
+            info!("Custom record {}: {}", record.tlv_type, record.value);
+        }
 
         match self
             .lsp_customer_service
@@ -87,4 +117,25 @@ where
             }
         }
     }
+    async fn payment_successful(
+        &self,
+        payment_hash: String,
+        _fee_paid_msat: Option<u64>,
+    ) -> Result<(), anyhow::Error> {
+        self.lsp_customer_service
+            .handle_keysend_succeeded(&payment_hash)
+            .await
+    }
+
+    async fn payment_failed(
+        &self,
+        payment_hash: String,
+        _reason: Option<String>,
+    ) -> Result<(), anyhow::Error> {
+        let mut client = self.client.clone();
+
+        self.lsp_customer_service
+            .handle_keysend_failed(&mut client, &payment_hash)
+            .await
+    }
 }